@@ -0,0 +1,26 @@
+-- 图片，name是qq客户端里[图片=xxx/]的xxx
+CREATE TABLE IF NOT EXISTS t_pic (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    name VARCHAR(255) NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE KEY uk_pic_name (name)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- 词
+CREATE TABLE IF NOT EXISTS t_word (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    word VARCHAR(255) NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE KEY uk_word_word (word)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- 图片与词的关联
+CREATE TABLE IF NOT EXISTS t_pic_word (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    id_pic BIGINT UNSIGNED NOT NULL,
+    id_word BIGINT UNSIGNED NOT NULL,
+    last_ts TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    UNIQUE KEY uk_pic_word (id_pic, id_word),
+    KEY idx_pic_word_word (id_word)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,12 @@
+-- 图片内容的hash，内容相同的图片只保留一张
+ALTER TABLE t_pic ADD COLUMN hash CHAR(32) NULL, ADD KEY idx_pic_hash (hash);
+
+-- 被合并掉的图片名，指向保留下来的那张图片
+CREATE TABLE IF NOT EXISTS t_pic_alias (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    name VARCHAR(255) NOT NULL,
+    id_pic BIGINT UNSIGNED NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE KEY uk_pic_alias_name (name),
+    KEY idx_pic_alias_pic (id_pic)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,6 @@
+-- 图片因为这个词被发出去的次数，用于按最常用排序
+ALTER TABLE t_pic_word ADD COLUMN use_count INT UNSIGNED NOT NULL DEFAULT 0;
+
+-- last_ts在set和发图时都会刷新，精确到毫秒才能分出先后
+-- 只在代码里显式刷新，去掉ON UPDATE，否则改use_count、合并图片之类的更新也会把它刷新掉
+ALTER TABLE t_pic_word MODIFY COLUMN last_ts TIMESTAMP(3) NOT NULL DEFAULT CURRENT_TIMESTAMP(3);
@@ -0,0 +1,9 @@
+-- 词的简体、拼音和拼音首字母，查询时用来匹配繁体字和拼音
+-- 已有的词在启动时补上
+ALTER TABLE t_word
+    ADD COLUMN simplified VARCHAR(255) NULL,
+    ADD COLUMN pinyin VARCHAR(768) NULL,
+    ADD COLUMN initials VARCHAR(255) NULL,
+    ADD KEY idx_word_simplified (simplified),
+    ADD KEY idx_word_pinyin (pinyin),
+    ADD KEY idx_word_initials (initials);
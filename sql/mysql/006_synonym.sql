@@ -0,0 +1,9 @@
+-- 同义词，id_group相同的词互为同义词，一个词只属于一组
+CREATE TABLE IF NOT EXISTS t_synonym (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    id_group BIGINT UNSIGNED NOT NULL,
+    word VARCHAR(255) NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE KEY uk_synonym_word (word),
+    KEY idx_synonym_group (id_group)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,41 @@
+-- 图片与词的关联所属的范围：global是全局图库，g:群号是某个群的，u:qq号是某个人私聊的
+-- 已有的关联都归到全局图库里
+-- mysql的DDL不能回滚，中途失败后会从这个版本重新执行，每一步都先检查是否已经做过
+SET @sql = IF(EXISTS (SELECT 1 FROM information_schema.COLUMNS
+                      WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = 't_pic_word' AND COLUMN_NAME = 'scope'),
+    'DO 0',
+    'ALTER TABLE t_pic_word ADD COLUMN scope VARCHAR(64) NOT NULL DEFAULT ''global'' AFTER id_word');
+PREPARE stmt FROM @sql;
+EXECUTE stmt;
+DEALLOCATE PREPARE stmt;
+
+-- 先加上带范围的唯一索引，再去掉原来(id_pic, id_word)上的，中间任何时候都有唯一约束
+SET @sql = IF(EXISTS (SELECT 1 FROM information_schema.STATISTICS
+                      WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = 't_pic_word' AND INDEX_NAME = 'uk_pic_word_scope'),
+    'DO 0',
+    'ALTER TABLE t_pic_word ADD UNIQUE KEY uk_pic_word_scope (id_pic, id_word, scope)');
+PREPARE stmt FROM @sql;
+EXECUTE stmt;
+DEALLOCATE PREPARE stmt;
+
+-- 原来的唯一索引不假定叫什么名字，按列从information_schema里查出来
+SET @uk = (SELECT INDEX_NAME
+           FROM information_schema.STATISTICS
+           WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = 't_pic_word' AND NON_UNIQUE = 0 AND INDEX_NAME <> 'PRIMARY'
+           GROUP BY INDEX_NAME
+           HAVING GROUP_CONCAT(COLUMN_NAME ORDER BY SEQ_IN_INDEX) = 'id_pic,id_word'
+           LIMIT 1);
+SET @sql = IF(@uk IS NULL,
+    'DO 0',
+    CONCAT('ALTER TABLE t_pic_word DROP INDEX `', REPLACE(@uk, '`', '``'), '`'));
+PREPARE stmt FROM @sql;
+EXECUTE stmt;
+DEALLOCATE PREPARE stmt;
+
+-- 每个范围的设置，没有记录的按默认值
+CREATE TABLE IF NOT EXISTS t_scope (
+    scope VARCHAR(64) NOT NULL,
+    -- 查询时是否也查全局图库
+    search_global TINYINT NOT NULL DEFAULT 1,
+    PRIMARY KEY (scope)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,6 @@
+-- 关联是谁在哪个群里什么时候加的，私聊加的group_id为空
+-- 已有的关联不知道是谁加的，tagger_id为空，created_ts为NULL
+ALTER TABLE t_pic_word
+    ADD COLUMN tagger_id VARCHAR(32) NOT NULL DEFAULT '' AFTER scope,
+    ADD COLUMN group_id VARCHAR(32) NOT NULL DEFAULT '' AFTER tagger_id,
+    ADD COLUMN created_ts TIMESTAMP(3) NULL DEFAULT NULL AFTER group_id;
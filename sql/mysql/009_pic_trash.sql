@@ -0,0 +1,2 @@
+-- delete只是把图片放进回收站，不为NULL的是放进去的时间，查询和random都不会发出这些图片
+ALTER TABLE t_pic ADD COLUMN deleted_ts TIMESTAMP(3) NULL DEFAULT NULL;
@@ -0,0 +1,7 @@
+-- 分词用的自定义词，管理员加上之后这些词不会被拆开
+CREATE TABLE IF NOT EXISTS t_dict_word (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    word VARCHAR(255) NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE KEY uk_dict_word (word)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
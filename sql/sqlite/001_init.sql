@@ -0,0 +1,21 @@
+-- 图片，name是qq客户端里[图片=xxx/]的xxx
+CREATE TABLE IF NOT EXISTS t_pic (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE
+);
+
+-- 词
+CREATE TABLE IF NOT EXISTS t_word (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    word TEXT NOT NULL UNIQUE
+);
+
+-- 图片与词的关联
+CREATE TABLE IF NOT EXISTS t_pic_word (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    id_pic INTEGER NOT NULL,
+    id_word INTEGER NOT NULL,
+    last_ts TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (id_pic, id_word)
+);
+CREATE INDEX IF NOT EXISTS idx_pic_word_word ON t_pic_word (id_word);
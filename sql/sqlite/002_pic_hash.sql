@@ -0,0 +1,11 @@
+-- 图片内容的hash，内容相同的图片只保留一张
+ALTER TABLE t_pic ADD COLUMN hash TEXT;
+CREATE INDEX IF NOT EXISTS idx_pic_hash ON t_pic (hash);
+
+-- 被合并掉的图片名，指向保留下来的那张图片
+CREATE TABLE IF NOT EXISTS t_pic_alias (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    id_pic INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_pic_alias_pic ON t_pic_alias (id_pic);
@@ -0,0 +1,2 @@
+-- 图片的感知hash(dHash)，用来找出缩放、重新压缩过的同一张图片
+ALTER TABLE t_pic ADD COLUMN phash INTEGER;
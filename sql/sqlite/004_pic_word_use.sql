@@ -0,0 +1,2 @@
+-- 图片因为这个词被发出去的次数，用于按最常用排序
+ALTER TABLE t_pic_word ADD COLUMN use_count INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,8 @@
+-- 词的简体、拼音和拼音首字母，查询时用来匹配繁体字和拼音
+-- 已有的词在启动时补上
+ALTER TABLE t_word ADD COLUMN simplified TEXT;
+ALTER TABLE t_word ADD COLUMN pinyin TEXT;
+ALTER TABLE t_word ADD COLUMN initials TEXT;
+CREATE INDEX IF NOT EXISTS idx_word_simplified ON t_word (simplified);
+CREATE INDEX IF NOT EXISTS idx_word_pinyin ON t_word (pinyin);
+CREATE INDEX IF NOT EXISTS idx_word_initials ON t_word (initials);
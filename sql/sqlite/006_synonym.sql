@@ -0,0 +1,7 @@
+-- 同义词，id_group相同的词互为同义词，一个词只属于一组
+CREATE TABLE IF NOT EXISTS t_synonym (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    id_group INTEGER NOT NULL,
+    word TEXT NOT NULL UNIQUE
+);
+CREATE INDEX IF NOT EXISTS idx_synonym_group ON t_synonym (id_group);
@@ -0,0 +1,23 @@
+-- 图片与词的关联所属的范围：global是全局图库，g:群号是某个群的，u:qq号是某个人私聊的
+-- 已有的关联都归到全局图库里，sqlite改不了唯一约束，只能重建表
+CREATE TABLE t_pic_word_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    id_pic INTEGER NOT NULL,
+    id_word INTEGER NOT NULL,
+    scope TEXT NOT NULL DEFAULT 'global',
+    last_ts TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    use_count INTEGER NOT NULL DEFAULT 0,
+    UNIQUE (id_pic, id_word, scope)
+);
+INSERT INTO t_pic_word_new (id, id_pic, id_word, last_ts, use_count)
+    SELECT id, id_pic, id_word, last_ts, use_count FROM t_pic_word;
+DROP TABLE t_pic_word;
+ALTER TABLE t_pic_word_new RENAME TO t_pic_word;
+CREATE INDEX IF NOT EXISTS idx_pic_word_word ON t_pic_word (id_word);
+
+-- 每个范围的设置，没有记录的按默认值
+CREATE TABLE IF NOT EXISTS t_scope (
+    scope TEXT NOT NULL PRIMARY KEY,
+    -- 查询时是否也查全局图库
+    search_global INTEGER NOT NULL DEFAULT 1
+);
@@ -0,0 +1,5 @@
+-- 关联是谁在哪个群里什么时候加的，私聊加的group_id为空
+-- 已有的关联不知道是谁加的，tagger_id为空，created_ts为NULL
+ALTER TABLE t_pic_word ADD COLUMN tagger_id TEXT NOT NULL DEFAULT '';
+ALTER TABLE t_pic_word ADD COLUMN group_id TEXT NOT NULL DEFAULT '';
+ALTER TABLE t_pic_word ADD COLUMN created_ts TIMESTAMP;
@@ -0,0 +1,5 @@
+-- 分词用的自定义词，管理员加上之后这些词不会被拆开
+CREATE TABLE IF NOT EXISTS t_dict_word (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    word TEXT NOT NULL UNIQUE
+);
@@ -0,0 +1,282 @@
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use db::{DbError, PicStore};
+use dump::{self, Format};
+use std::cmp::Reverse;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const PREFIX: &str = "tutu-";
+const SUFFIX: &str = ".jsonl";
+const TIME_FORMAT: &str = "%Y%m%d-%H%M%S%.3f";
+// 以前的备份文件名里只到秒
+const OLD_TIME_FORMAT: &str = "%Y%m%d-%H%M%S";
+// 后台线程每隔这么久看一次是否该备份了
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// 定时把整个图库导出到备份目录里，文件名是tutu-时间.jsonl，格式与export导出的一样
+#[derive(Debug)]
+pub struct Backup {
+    dir: PathBuf,
+    // 每隔这么多小时备份一次
+    interval_hours: u32,
+    // 最多保留这么多份，0表示不限
+    keep: usize,
+    // 超过这么多天的删掉，0表示不限
+    keep_days: u32,
+    // 最近一次成功备份的时间，重启之后从目录里最新的一份算起
+    last: Option<DateTime<Local>>,
+}
+
+impl Backup {
+    pub fn new(dir: &str, interval_hours: u32, keep: usize, keep_days: u32) -> Backup {
+        let mut backup = Backup { dir: PathBuf::from(dir), interval_hours, keep, keep_days, last: None };
+        backup.last = backup.list().unwrap_or_default().first().map(|t| t.1);
+        return backup;
+    }
+
+    // 离上次备份已经超过间隔了
+    pub fn is_due(&self) -> bool {
+        if self.interval_hours == 0 {
+            return false;
+        }
+        return match self.last {
+            Some(t) => Local::now().signed_duration_since(t).num_hours() >= self.interval_hours as i64,
+            None => true
+        };
+    }
+
+    // 立即备份一次并清理旧的备份，返回备份文件名和导出的记录条数
+    pub fn run(&mut self, store: &mut dyn PicStore) -> std::result::Result<(String, usize), DbError> {
+        fs::create_dir_all(&self.dir)?;
+
+        // 先写到临时文件里，写完再改名，中途失败不会留下半个备份
+        let now = Local::now();
+        let name = format!("{}{}{}", PREFIX, now.format(TIME_FORMAT), SUFFIX);
+        let path = self.dir.join(&name);
+        if path.exists() {
+            return Err(DbError::from(Error::new(ErrorKind::AlreadyExists, format!("backup exists: {}", name))));
+        }
+        let tmp = self.dir.join(format!("{}.tmp", name));
+        let count = match dump::export(store, &path_str(&tmp)?, Format::Jsonl) {
+            Ok(t) => t,
+            Err(t) => {
+                let _ = fs::remove_file(&tmp);
+                return Err(t);
+            }
+        };
+        fs::rename(&tmp, &path)?;
+
+        // 失败了不算备份过，下次检查时会再试
+        self.last = Some(now);
+        self.rotate()?;
+        return Ok((name, count));
+    }
+
+    // 备份目录里的所有备份及备份时间，最新的在前
+    pub fn list(&self) -> Result<Vec<(String, DateTime<Local>)>> {
+        let mut list = vec!();
+        if !self.dir.is_dir() {
+            return Ok(list);
+        }
+        for entry in fs::read_dir(&self.dir)? {
+            let name = match entry?.file_name().into_string() {
+                Ok(t) => t,
+                Err(_) => continue
+            };
+            if let Some(t) = parse_time(&name) {
+                list.push((name, t));
+            }
+        }
+        list.sort_by_key(|t| Reverse(t.1));
+        return Ok(list);
+    }
+
+    // 把某一份备份恢复到空的图库里，latest表示最新的一份
+    // 图库里已经有数据时不恢复，免得备份和现有的数据混在一起
+    pub fn restore(&self, name: &str, store: &mut dyn PicStore) -> std::result::Result<(String, usize), DbError> {
+        let name = if name == "latest" {
+            match self.list()?.into_iter().next() {
+                Some(t) => t.0,
+                None => return Err(DbError::from(Error::new(ErrorKind::NotFound, "no backup")))
+            }
+        } else {
+            String::from(name)
+        };
+        if parse_time(&name).is_none() {
+            return Err(DbError::from(Error::new(ErrorKind::InvalidInput, format!("bad backup name: {}", name))));
+        }
+
+        if !store.export()?.is_empty() {
+            return Err(DbError::from(Error::new(ErrorKind::AlreadyExists, "store is not empty")));
+        }
+        let count = dump::import(store, &path_str(&self.dir.join(&name))?, Format::Jsonl)?;
+        return Ok((name, count));
+    }
+
+    // 超出份数的和太旧的备份删掉，最新的一份总是保留
+    fn rotate(&self) -> Result<()> {
+        let now = Local::now();
+        for (i, (name, time)) in self.list()?.into_iter().enumerate() {
+            if i == 0 {
+                continue;
+            }
+            let too_many = self.keep > 0 && i >= self.keep;
+            let too_old = self.keep_days > 0 && now.signed_duration_since(time).num_days() >= self.keep_days as i64;
+            if too_many || too_old {
+                fs::remove_file(self.dir.join(&name))?;
+                println!("backup removed: {}", name);
+            }
+        }
+        return Ok(());
+    }
+}
+
+// 定时备份在后台线程里做，用open打开的另一个连接导出，导出再久也不会卡住bot处理请求
+pub fn start<F>(backup: Arc<Mutex<Backup>>, open: F) -> thread::JoinHandle<()>
+    where F: FnOnce() -> std::result::Result<Box<dyn PicStore>, DbError> + Send + 'static
+{
+    return thread::spawn(move || {
+        let mut store = match open() {
+            Ok(t) => t,
+            Err(t) => {
+                println!("backup fail: {}", t);
+                return;
+            }
+        };
+        loop {
+            if let Ok(mut backup) = backup.lock() {
+                if backup.is_due() {
+                    match backup.run(&mut *store) {
+                        Ok(t) => println!("backup ok: {} {} records", t.0, t.1),
+                        Err(t) => println!("backup fail: {}", t)
+                    }
+                }
+            }
+            thread::sleep(CHECK_INTERVAL);
+        }
+    });
+}
+
+// 从文件名里取出备份时间，不是备份文件的返回None
+fn parse_time(name: &str) -> Option<DateTime<Local>> {
+    if !name.starts_with(PREFIX) || !name.ends_with(SUFFIX) {
+        return None;
+    }
+    let time = &name[PREFIX.len()..name.len() - SUFFIX.len()];
+    let time = NaiveDateTime::parse_from_str(time, TIME_FORMAT)
+        .or_else(|_| NaiveDateTime::parse_from_str(time, OLD_TIME_FORMAT))
+        .ok()?;
+    return Local.from_local_datetime(&time).earliest();
+}
+
+fn path_str(path: &Path) -> Result<String> {
+    return match path.to_str() {
+        Some(t) => Ok(String::from(t)),
+        None => Err(Error::new(ErrorKind::InvalidInput, "bad backup dir"))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use db::{MemoryStore, Tagger, GLOBAL_SCOPE};
+    use std::{env, process};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tutuv3-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    // 在目录里放一份days天前的空备份
+    fn old_backup(dir: &Path, days: i64) -> String {
+        let time = Local::now() - Duration::days(days);
+        let name = format!("{}{}{}", PREFIX, time.format(TIME_FORMAT), SUFFIX);
+        fs::write(dir.join(&name), b"").unwrap();
+        return name;
+    }
+
+    fn names(backup: &Backup) -> Vec<String> {
+        return backup.list().unwrap().into_iter().map(|t| t.0).collect();
+    }
+
+    #[test]
+    fn rotate_by_count() {
+        let dir = temp_dir("backup-count");
+        let kept = old_backup(&dir, 1);
+        old_backup(&dir, 2);
+        old_backup(&dir, 3);
+        fs::write(dir.join("other.txt"), b"").unwrap();
+
+        let mut backup = Backup::new(dir.to_str().unwrap(), 24, 2, 0);
+        let (name, count) = backup.run(&mut MemoryStore::new()).unwrap();
+        assert_eq!(count, 0);
+        assert_eq!(names(&backup), vec!(name, kept));
+        // 不是备份的文件不动
+        assert!(dir.join("other.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotate_by_age() {
+        let dir = temp_dir("backup-age");
+        let kept = old_backup(&dir, 2);
+        old_backup(&dir, 10);
+
+        let mut backup = Backup::new(dir.to_str().unwrap(), 24, 0, 7);
+        // 最新的一份再旧也留着
+        backup.rotate().unwrap();
+        assert_eq!(names(&backup), vec!(kept.clone()));
+
+        old_backup(&dir, 8);
+        let (name, _) = backup.run(&mut MemoryStore::new()).unwrap();
+        assert_eq!(names(&backup), vec!(name, kept));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn due() {
+        let dir = temp_dir("backup-due");
+        assert!(Backup::new(dir.to_str().unwrap(), 24, 0, 0).is_due());
+        assert!(!Backup::new(dir.to_str().unwrap(), 0, 0, 0).is_due());
+        // 重启之后从目录里最新的一份算起
+        old_backup(&dir, 0);
+        assert!(!Backup::new(dir.to_str().unwrap(), 24, 0, 0).is_due());
+        fs::remove_dir_all(&dir).unwrap();
+
+        // 备份失败了下次还要再试
+        let file = env::temp_dir().join(format!("tutuv3-backup-file-{}", process::id()));
+        fs::write(&file, b"").unwrap();
+        let mut backup = Backup::new(file.join("backup").to_str().unwrap(), 24, 0, 0);
+        assert!(backup.run(&mut MemoryStore::new()).is_err());
+        assert!(backup.is_due());
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn restore() {
+        let dir = temp_dir("backup-restore");
+        let mut store = MemoryStore::new();
+        store.append_word(GLOBAL_SCOPE, "a", "猫 狗", &Tagger::default()).unwrap();
+        store.add_synonyms(&["猫", "喵"]).unwrap();
+        let mut backup = Backup::new(dir.to_str().unwrap(), 24, 0, 0);
+        assert!(backup.restore("latest", &mut MemoryStore::new()).is_err());
+        let (name, count) = backup.run(&mut store).unwrap();
+
+        let mut restored = MemoryStore::new();
+        assert_eq!(backup.restore("latest", &mut restored).unwrap(), (name.clone(), count));
+        assert_eq!(restored.export().unwrap(), store.export().unwrap());
+
+        // 已经有数据的图库不恢复，不是备份文件名的不认
+        let err = backup.restore(&name, &mut store).unwrap_err();
+        assert!(err.to_string().contains("store is not empty"));
+        assert!(backup.restore("../tutu.db", &mut MemoryStore::new()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
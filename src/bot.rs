@@ -1,6 +1,12 @@
-use db;
-use db::DbInfo;
+use backup::Backup;
+use db::{self, similar_words, DbError, Orphans, PicStore, Rank, TagRecord, Tagger, WordQuery};
+use image_store::ImageStore;
+use journal::{Journal, Operation, OperationKind};
+use search::SearchIndex;
+use segment::Segmenter;
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 const PIC_START: &str = "[图片=";
 const PIC_END: &str = "/]";
@@ -14,11 +20,11 @@ pub struct BotRequest {
     sender_id: String,
     group_id: String,
     is_in_group: bool,
+    // set的图片放在哪个范围里，查询时也先查这个范围
+    scope: String,
 
     word: String,
     pic: String,
-
-    db: DbInfo,
 }
 
 #[derive(Debug)]
@@ -30,6 +36,7 @@ enum BotRequestType {
     About,
     RecordPrevImg,
     Set,
+    Unset,
     Query,
     Random,
 
@@ -38,34 +45,71 @@ enum BotRequestType {
     Info,
     Count,
     Clean,
+    Dedup,
+    Similar,
+    Synonym,
+    Scope,
+    Undo,
+    Trash,
+    Restore,
+    Purge,
+    Backup,
+    Dict,
 }
 
 #[derive(Debug)]
 struct BotSession {
     prev_pic: String,
+    // 上一次clean列出来的内容，等待clean confirm
+    clean_plan: Option<CleanPlan>,
+    // 管理员切换到的范围，私聊时代替自己的范围
+    scope: Option<String>,
 }
 
 #[derive(Debug)]
+struct CleanPlan {
+    orphans: Orphans,
+    files: Vec<String>,
+}
+
+impl BotSession {
+    fn new() -> BotSession {
+        return BotSession { prev_pic: String::new(), clean_plan: None, scope: None };
+    }
+}
+
 pub struct BotGlobals {
     admin_id: String,
     sessions: HashMap<String, BotSession>,
-    db_user: String,
-    db_pwd: String,
+    store: Box<dyn PicStore>,
+    images: Option<ImageStore>,
+    // set/unset/replace/delete的记录，用于undo
+    journal: Journal,
+    // 回收站里的图片放了这么多天之后彻底删除，0表示不自动删除
+    trash_days: u32,
+    last_purge: Option<Instant>,
+    // 不设置备份目录就不备份
+    backup: Option<Arc<Mutex<Backup>>>,
+    // 用倒排索引代替数据库查询，不设置就直接查数据库
+    search: Option<SearchIndex>,
+    // 不开分词就按空白分隔
+    segmenter: Option<Segmenter>,
 }
 
 impl BotGlobals {
-    pub fn new(admin_id: String, db_user: String, db_pwd: String) -> BotGlobals {
-        return BotGlobals { admin_id, sessions: HashMap::new(), db_user, db_pwd };
+    pub fn new(admin_id: String, store: Box<dyn PicStore>, images: Option<ImageStore>, trash_days: u32, backup: Option<Arc<Mutex<Backup>>>, search: Option<SearchIndex>,
+               segmenter: Option<Segmenter>) -> BotGlobals {
+        return BotGlobals { admin_id, sessions: HashMap::new(), store, images, journal: Journal::new(), trash_days, last_purge: None, backup, search, segmenter };
     }
 }
 
 impl BotRequest {
     pub fn new(params: &HashMap<String, String>, globals: &BotGlobals) -> BotRequest {
-        let message = get(&params, "Message");
-        let sender_id = get(&params, "QQ");
-        let group_id = get(&params, "ExternalId");
-        let bot_self_id = get(&params, "RobotQQ");
-        let bot_self_name = get(&params, "Name");
+        let message = get(params, "Message");
+        let sender_id = get(params, "QQ");
+        let group_id = get(params, "ExternalId");
+        let bot_self_id = get(params, "RobotQQ");
+        let bot_self_name = get(params, "Name");
 
         // ignore self message
         if bot_self_id == sender_id {
@@ -106,9 +150,11 @@ impl BotRequest {
                     "help" => BotRequestType::Help,
                     "about" => BotRequestType::About,
                     "set" => BotRequestType::Set,
+                    "unset" => BotRequestType::Unset,
                     "random" => BotRequestType::Random,
+                    "undo" => BotRequestType::Undo,
                     _ => if pic.is_empty() {
-                        word = cmd;
+                        word = String::from(text.trim());
                         BotRequestType::Query
                     } else { BotRequestType::RecordPrevImg }
                 }
@@ -121,14 +167,25 @@ impl BotRequest {
                     "help" => BotRequestType::HelpAdmin,
                     "about" => BotRequestType::About,
                     "set" => BotRequestType::Set,
+                    "unset" => BotRequestType::Unset,
                     "random" => BotRequestType::Random,
                     "delete" => BotRequestType::Delete,
                     "replace" => BotRequestType::Replace,
                     "info" => BotRequestType::Info,
                     "count" => BotRequestType::Count,
                     "clean" => BotRequestType::Clean,
+                    "dedup" => BotRequestType::Dedup,
+                    "similar" => BotRequestType::Similar,
+                    "synonym" => BotRequestType::Synonym,
+                    "scope" => BotRequestType::Scope,
+                    "undo" => BotRequestType::Undo,
+                    "trash" => BotRequestType::Trash,
+                    "restore" => BotRequestType::Restore,
+                    "purge" => BotRequestType::Purge,
+                    "backup" => BotRequestType::Backup,
+                    "dict" => BotRequestType::Dict,
                     _ => if pic.is_empty() {
-                        word = cmd;
+                        word = String::from(text.trim());
                         BotRequestType::Query
                     } else { BotRequestType::RecordPrevImg }
                 }
@@ -137,17 +194,18 @@ impl BotRequest {
             }
         };
 
+        let scope = if is_in_group { db::group_scope(&group_id) } else { db::user_scope(&sender_id) };
+
         return BotRequest {
             req_type,
 
             sender_id,
             group_id,
             is_in_group,
+            scope,
 
             word,
             pic,
-
-            db: DbInfo::new(&globals.db_user, &globals.db_pwd),
         };
 
         fn get(map: &HashMap<String, String>, k: &str) -> String {
@@ -191,13 +249,17 @@ impl BotRequest {
             sender_id: String::new(),
             group_id: String::new(),
             is_in_group: false,
+            scope: String::new(),
 
             word: String::new(),
             pic: String::new(),
-
-            db: DbInfo::empty(),
         };
     }
+
+    // 记在set的词上，群里发的记下群号
+    fn tagger(&self) -> Tagger {
+        return Tagger { user_id: self.sender_id.clone(), group_id: self.group_id.clone() };
+    }
 }
 
 #[derive(Debug)]
@@ -236,37 +298,133 @@ impl BotResponse {
 }
 
 pub fn process_request(req: &mut BotRequest, globals: &mut BotGlobals) -> Vec<BotResponse> {
+    purge_expired_trash(globals);
+
     let session_key = if req.is_in_group {
         format!("g{}", req.group_id)
     } else {
-        format!("{}", req.sender_id)
+        req.sender_id.clone()
     };
 
     if !globals.sessions.contains_key(session_key.as_str()) {
-        globals.sessions.insert(session_key.clone(), BotSession { prev_pic: String::new() });
+        globals.sessions.insert(session_key.clone(), BotSession::new());
     }
-    let mut session = globals.sessions.get_mut(&session_key).unwrap();
+    let session = globals.sessions.get_mut(&session_key).unwrap();
+    let store = &mut *globals.store;
+    let images = &globals.images;
+    let journal = &mut globals.journal;
+    let search = &mut globals.search;
+    let segmenter = &mut globals.segmenter;
+    let is_admin = req.sender_id == globals.admin_id;
 
     if req.pic.is_empty() {
         req.pic = session.prev_pic.clone();
     }
+    if let Some(ref t) = session.scope {
+        req.scope = t.clone();
+    }
+
+    // 被合并掉的图片换成保留下来的那张
+    match req.req_type {
+        BotRequestType::Set | BotRequestType::Unset | BotRequestType::Delete | BotRequestType::Replace | BotRequestType::Info
+        | BotRequestType::Restore | BotRequestType::Purge if !req.pic.is_empty() => {
+            if let Ok(t) = store.resolve_pic(&req.pic) {
+                req.pic = t;
+            }
+        }
+        _ => {}
+    }
+
+    // 一句话切成几个词再set，unset同样切开，set时用的话原样unset就能去掉
+    match req.req_type {
+        BotRequestType::Set | BotRequestType::Unset | BotRequestType::Replace => if let Some(ref t) = segmenter {
+            req.word = t.split_tags(&req.word);
+        },
+        _ => {}
+    }
 
     let req_type = &req.req_type;
-    return match req_type {
+    let responses = match req_type {
         BotRequestType::Ignore => vec!(),
-        BotRequestType::Help => BotResponse::simple(handle_help(), &req),
-        BotRequestType::HelpAdmin => BotResponse::simple(handle_help_admin(), &req),
-        BotRequestType::About => BotResponse::simple(handle_about(), &req),
-        BotRequestType::RecordPrevImg => handle_record_prev_img(&req, &mut session),
-        BotRequestType::Set => BotResponse::simple(handle_set(&req), &req),
-        BotRequestType::Query => handle_query(&req),
-        BotRequestType::Random => BotResponse::simple(handle_random(&req), &req),
-        BotRequestType::Delete => BotResponse::simple(handle_delete(&req), &req),
-        BotRequestType::Replace => BotResponse::simple(handle_replace(&req), &req),
-        BotRequestType::Info => BotResponse::simple(handle_info(&req), &req),
-        BotRequestType::Count => BotResponse::simple(handle_count(&req), &req),
-        BotRequestType::Clean => BotResponse::simple(handle_clean(&req), &req),
+        BotRequestType::Help => BotResponse::simple(handle_help(), req),
+        BotRequestType::HelpAdmin => BotResponse::simple(handle_help_admin(), req),
+        BotRequestType::About => BotResponse::simple(handle_about(), req),
+        BotRequestType::RecordPrevImg => handle_record_prev_img(req, session),
+        BotRequestType::Set => BotResponse::simple(handle_set(req, &session_key, journal, store, images), req),
+        BotRequestType::Unset => BotResponse::simple(handle_unset(req, &session_key, journal, store), req),
+        BotRequestType::Query => handle_query(req, store, images, search, segmenter),
+        BotRequestType::Random => BotResponse::simple(handle_random(req, store, images), req),
+        BotRequestType::Delete => BotResponse::simple(handle_delete(req, &session_key, journal, store), req),
+        BotRequestType::Replace => BotResponse::simple(handle_replace(req, &session_key, journal, store, images), req),
+        BotRequestType::Info => BotResponse::simple(handle_info(req, store), req),
+        BotRequestType::Count => BotResponse::simple(handle_count(store), req),
+        BotRequestType::Clean => BotResponse::simple(handle_clean(req, session, store, images), req),
+        BotRequestType::Dedup => BotResponse::simple(handle_dedup(store, images), req),
+        BotRequestType::Similar => BotResponse::simple(handle_similar(req, store, images), req),
+        BotRequestType::Synonym => BotResponse::simple(handle_synonym(req, store), req),
+        BotRequestType::Scope => BotResponse::simple(handle_scope(req, session, store), req),
+        BotRequestType::Undo => BotResponse::simple(handle_undo(req, &session_key, is_admin, journal, store), req),
+        BotRequestType::Trash => BotResponse::simple(handle_trash(store), req),
+        BotRequestType::Restore => BotResponse::simple(handle_restore(req, store), req),
+        BotRequestType::Purge => BotResponse::simple(handle_purge(req, store), req),
+        BotRequestType::Backup => BotResponse::simple(handle_backup(req, store, &globals.backup), req),
+        BotRequestType::Dict => BotResponse::simple(handle_dict(req, store, segmenter), req),
+    };
+
+    if let Some(ref mut t) = search {
+        refresh_search_index(req, store, t);
+    }
+    return responses;
+}
+
+// 改动了图片上的词的请求处理完之后更新索引，只改了一张图片的只重新读这一张
+fn refresh_search_index(req: &BotRequest, store: &mut dyn PicStore, search: &mut SearchIndex) {
+    let pic = match req.req_type {
+        BotRequestType::Set | BotRequestType::Unset | BotRequestType::Replace | BotRequestType::Delete => req.pic.as_str(),
+        BotRequestType::Restore => if req.word.is_empty() { req.pic.as_str() } else { req.word.as_str() },
+        BotRequestType::Undo | BotRequestType::Clean | BotRequestType::Dedup | BotRequestType::Similar => {
+            search.mark_stale();
+            return;
+        }
+        _ => return
     };
+    if pic.is_empty() {
+        return;
+    }
+
+    // set之后图片可能被合并到了另一张上
+    let mut pics = vec!(String::from(pic));
+    if let Ok(t) = store.resolve_pic(pic) {
+        if t != pic {
+            pics.push(t);
+        }
+    }
+    for pic in pics {
+        if let Err(t) = search.refresh_pic(&pic, store) {
+            println!("refresh search index fail: {}", t);
+            search.mark_stale();
+        }
+    }
+}
+
+// 回收站里放得太久的图片彻底删除，最多一小时检查一次
+fn purge_expired_trash(globals: &mut BotGlobals) {
+    if globals.trash_days == 0 {
+        return;
+    }
+    if let Some(t) = globals.last_purge {
+        if t.elapsed() < Duration::from_secs(3600) {
+            return;
+        }
+    }
+    globals.last_purge = Some(Instant::now());
+
+    match globals.store.purge_trash(globals.trash_days) {
+        Ok(t) => if !t.is_empty() {
+            println!("trash purged: {}", t.join(" "));
+        },
+        Err(t) => println!("purge trash fail: {}", t)
+    }
 }
 
 fn handle_help() -> String {
@@ -275,13 +433,21 @@ fn handle_help() -> String {
 * help
   显示本说明
 * 直接发文字
-  查询包含指定文字的图片
+  查询包含指定文字的图片，也可以用繁体字、拼音或者拼音首字母
+* 文字1 文字2 / 文字1|文字2 / 文字1 -文字2
+  查询同时包含 / 包含其中任意一个 / 包含前者但不包含后者的图片
+* !new 文字 / !hot 文字 / !old 文字
+  查询时优先发最近用过的 / 最常用的 / 最久没发过的图片，默认!new
 * set 字符串
   设置前一张图片对应的文字
 * set [图片] 字符串 或 set 字符串 [图片]
   设置指定图片对应的文字
+* unset 字符串 或 unset [图片] 字符串
+  去掉前一张图片或者指定图片上的这些文字
 * random
   随机输出一张图片
+* undo
+  撤销自己在这里最近一次的set/unset
 * about
   显示版本说明
  "
@@ -294,11 +460,17 @@ fn handle_help_admin() -> String {
 * help
   显示本说明
 * 直接发文字
-  查询包含指定文字的图片
+  查询包含指定文字的图片，也可以用繁体字、拼音或者拼音首字母
+* 文字1 文字2 / 文字1|文字2 / 文字1 -文字2
+  查询同时包含 / 包含其中任意一个 / 包含前者但不包含后者的图片
+* !new 文字 / !hot 文字 / !old 文字
+  查询时优先发最近用过的 / 最常用的 / 最久没发过的图片，默认!new
 * set 字符串
   设置前一张图片对应的文字
 * set [图片] 字符串 或 set 字符串 [图片]
   设置指定图片对应的文字
+* unset 字符串 或 unset [图片] 字符串
+  去掉前一张图片或者指定图片上的这些文字
 * random
   随机输出一张图片
 * about
@@ -307,13 +479,47 @@ fn handle_help_admin() -> String {
 * replace
   替换一张图片对应的文字（set命令是追加）
 * delete [图片]
-  从数据库中删除指定的图片信息
+  把指定的图片放进回收站，查询和random都不会再发出来
+* trash
+  列出回收站里的图片
+* restore [图片] 或 restore 图片名
+  把图片从回收站里拿回来，回收站里的图片重新set也会被拿回来
+* purge [图片] 或 purge 图片名 / purge all
+  彻底删除回收站里的一张 / 所有图片，图片文件由clean删除
+* dict / dict add 词1 词2 / dict remove 词
+  列出 / 添加 / 删除分词用的自定义词，加上的词set和查询时不会被拆开，segment=on时才分词
+* backup / backup list
+  立即备份一次 / 列出备份目录里的备份，启动时用restore=备份名或restore=latest恢复到空的数据库里
 * info [图片]
-  查询一张图片下挂的所有词
+  查询一张图片下挂的所有词，以及每个词是谁在什么时候加的
 * count
   查询各项数据指标
 * clean
-  删除掉没有被引用的图片文件
+  列出没有词的图片、没有被引用的词和图片文件
+* clean confirm
+  删除上一次clean列出来的内容
+* dedup
+  按图片内容合并重复的图片
+* similar [相似度]
+  列出看起来差不多的图片，相似度默认90（%）
+* similar merge [相似度]
+  把每一组差不多的图片合并成一张
+* synonym add 词1 词2 ...
+  把这些词设为同义词，查其中一个词时也能查到其它词的图片
+* synonym remove 词
+  把词从所在的同义词组里去掉
+* synonym list
+  列出所有的同义词组
+* scope
+  显示私聊时set和查询用的范围，默认是自己私聊的范围
+* scope global / scope group 群号 / scope user qq号 / scope reset
+  切换到全局图库 / 某个群 / 某个人私聊的范围 / 切换回自己的范围
+* scope search_global on|off
+  设置当前范围查询时是否也查全局图库
+* undo
+  撤销自己最近一次的set/unset/replace/delete，每人最多记最近10次
+* undo list / undo 编号
+  列出所有人最近的set/unset/replace/delete / 撤销其中的某一次
  "
     );
 }
@@ -335,7 +541,7 @@ fn handle_record_prev_img(req: &BotRequest, session: &mut BotSession) -> Vec<Bot
     return vec!();
 }
 
-fn handle_set(req: &BotRequest) -> String {
+fn handle_set(req: &BotRequest, session_key: &str, journal: &mut Journal, store: &mut dyn PicStore, images: &Option<ImageStore>) -> String {
     if req.pic.is_empty() {
         return String::from("set fail: no pic");
     }
@@ -343,57 +549,197 @@ fn handle_set(req: &BotRequest) -> String {
         return String::from("set fail: no text");
     }
 
-    let result = db::append_word(&req.pic, &req.word, &req.db);
+    // 已经有的词undo时不能去掉，图片文件与已有的图片相同时set完会合并过去，那张图片上原来就有的词也一样
+    let (hash, saved) = save_pic_file(&req.pic, images);
+    let mut before = match scope_tags(&req.pic, &req.scope, store) {
+        Ok(t) => t,
+        Err(t) => return format!("set fail: {}", t)
+    };
+    if let Some(ref hash) = hash {
+        let same = match store.find_pic_by_hash(&req.pic, hash) {
+            Ok(t) => t,
+            Err(t) => return format!("set fail: {}", t)
+        };
+        if let Some(t) = same {
+            match scope_tags(&t, &req.scope, store) {
+                Ok(t) => before.extend(t),
+                Err(t) => return format!("set fail: {}", t)
+            }
+        }
+    }
+    let mut added: Vec<String> = vec!();
+    for word in req.word.split_whitespace() {
+        if !before.iter().any(|t| t.word == word) && !added.iter().any(|t| t == word) {
+            added.push(String::from(word));
+        }
+    }
+
+    // 追加词和按内容去重在同一个事务里，不会留下只做了一半的合并
+    let result = store.append_word_dedup(&req.scope, &req.pic, &req.word, &req.tagger(), hash.as_deref());
     return match result {
-        Ok(_) => String::from("set ok"),
+        Ok(Some(t)) => {
+            // undo时从合并到的那张图片上去掉
+            journal.record(Operation { added, ..Operation::new(session_key, &req.sender_id, OperationKind::Set, &t, &req.scope) });
+            format!("set ok{}, same pic as {}", saved, t)
+        }
+        Ok(None) => {
+            journal.record(Operation { added, ..Operation::new(session_key, &req.sender_id, OperationKind::Set, &req.pic, &req.scope) });
+            if let (Some(_), Some(t)) = (&hash, images) {
+                save_pic_phash(&req.pic, store, t);
+            }
+            format!("set ok{}", saved)
+        }
         Err(t) => format!("set fail: {}", t)
     };
 }
 
-fn handle_query(req: &BotRequest) -> Vec<BotResponse> {
+fn handle_unset(req: &BotRequest, session_key: &str, journal: &mut Journal, store: &mut dyn PicStore) -> String {
+    if req.pic.is_empty() {
+        return String::from("unset fail: no pic");
+    }
     if req.word.is_empty() {
-        return BotResponse::simple(String::from("query fail: no text"), req);
+        return String::from("unset fail: no text");
+    }
+
+    // 只去掉图片在当前范围里确实有的词，undo时按原来是谁加的加回去
+    let mut before = match scope_tags(&req.pic, &req.scope, store) {
+        Ok(t) => t,
+        Err(t) => return format!("unset fail: {}", t)
+    };
+    let words: Vec<&str> = req.word.split_whitespace().collect();
+    before.retain(|t| words.contains(&t.word.as_str()));
+    if before.is_empty() {
+        return format!("unset fail: no such word: {}", words.join(" "));
     }
+    let removed: Vec<String> = before.iter().map(|t| t.word.clone()).collect();
 
-    let result = db::query_pic(&req.word, &req.db);
+    let result = store.remove_word(&req.scope, &req.pic, &removed.join(" "));
     return match result {
+        Ok(_) => {
+            journal.record(Operation { removed: before, ..Operation::new(session_key, &req.sender_id, OperationKind::Unset, &req.pic, &req.scope) });
+            format!("unset ok: removed {}", removed.join(" "))
+        }
+        Err(t) => format!("unset fail: {}", t)
+    };
+}
+
+fn handle_query(req: &BotRequest, store: &mut dyn PicStore, images: &Option<ImageStore>, search: &mut Option<SearchIndex>,
+                segmenter: &Option<Segmenter>) -> Vec<BotResponse> {
+    if req.word.is_empty() {
+        return BotResponse::simple(String::from("query fail: no text"), req);
+    }
+
+    let (rank, text) = parse_rank(&req.word);
+    let mut query = WordQuery::parse(text);
+    if let Some(ref t) = segmenter {
+        query = t.split_query(&query);
+    }
+    if query.is_empty() {
+        return BotResponse::simple(String::from("query fail: no text"), req);
+    }
+    let scopes = match search_scopes(&req.scope, store) {
+        Ok(t) => t,
+        Err(t) => return BotResponse::simple(format!("query fail: {}", t), req)
+    };
+    // 繁体字和拼音也能查到
+    let query = match query.with_forms(store) {
+        Ok(t) => t,
+        Err(t) => return BotResponse::simple(format!("query fail: {}", t), req)
+    };
+
+    // 先按原样查，完全匹配的优先
+    match find_pics(&scopes, &query, rank, store, search) {
+        Ok(t) => if !t.is_empty() {
+            return build_query_output(&t, &scopes, &query, req, store, images, search);
+        },
+        Err(t) => return BotResponse::simple(format!("query fail: {}", t), req)
+    }
+
+    // 查不到的话再加上包含这些文字的词和相近的词查一次
+    let mut words: Vec<String> = vec!();
+    for word in query.words() {
+        match store.list_similar_words(&scopes, word) {
+            Ok(t) => for t in t {
+                if !words.contains(&t) {
+                    words.push(t);
+                }
+            },
+            Err(t) => return BotResponse::simple(format!("query fail: {}", t), req)
+        }
+    }
+    let mut hints: Vec<&str> = vec!();
+    for word in query.words() {
+        for t in similar_words(word, &words) {
+            if !hints.contains(&t) {
+                hints.push(t);
+            }
+        }
+    }
+    if hints.is_empty() {
+        return BotResponse::simple(String::from("query fail: not found"), req);
+    }
+
+    let fuzzy = query.fuzzy(&words);
+    return match find_pics(&scopes, &fuzzy, rank, store, search) {
         Ok(t) => if t.is_empty() {
-            BotResponse::simple(String::from("query fail: not found"), req)
+            BotResponse::simple(format!("query fail: not found, did you mean: {}", hints.join(" ")), req)
         } else {
-            t.iter()
-                .map(|pic| build_pic_output(pic))
-                .map(|text| BotResponse::new(text, req))
-                .collect()
-        }
+            let mut responses = build_query_output(&t, &scopes, &fuzzy, req, store, images, search);
+            responses.push(BotResponse::new(format!("did you mean: {}", hints.join(" ")), req));
+            responses
+        },
         Err(t) => BotResponse::simple(format!("query fail: {}", t), req)
     };
+
+    // 开头的!new、!hot、!old指定挑图片的方式
+    fn parse_rank(text: &str) -> (Rank, &str) {
+        let text = text.trim();
+        let (head, tail) = match text.find(char::is_whitespace) {
+            Some(t) => (&text[..t], text[t..].trim()),
+            None => (text, "")
+        };
+        return match head {
+            "!new" => (Rank::Newest, tail),
+            "!hot" => (Rank::MostUsed, tail),
+            "!old" => (Rank::LeastRecent, tail),
+            _ => (Rank::Newest, text)
+        };
+    }
 }
 
-fn handle_random(req: &BotRequest) -> String {
-    let result = db::random_pic(&req.db);
+fn handle_random(req: &BotRequest, store: &mut dyn PicStore, images: &Option<ImageStore>) -> String {
+    let result = search_scopes(&req.scope, store).and_then(|t| store.random_pic(&t));
     return match result {
         Ok(t) => if t.is_empty() {
-            format!("random fail: db empty")
+            String::from("random fail: db empty")
         } else {
-            build_pic_output(&t)
+            build_pic_output(&t, images)
         },
         Err(t) => format!("random fail: {}", t)
     };
 }
 
-fn handle_delete(req: &BotRequest) -> String {
+fn handle_delete(req: &BotRequest, session_key: &str, journal: &mut Journal, store: &mut dyn PicStore) -> String {
     if req.pic.is_empty() {
         return String::from("delete fail: no pic");
     }
 
-    let result = db::delete_pic(&req.pic, &req.db);
+    let before = match store.list_pic_tags(&req.pic) {
+        Ok(t) => t,
+        Err(t) => return format!("delete fail: {}", t)
+    };
+
+    let result = store.delete_pic(&req.pic);
     return match result {
-        Ok(_) => String::from("delete ok"),
+        Ok(_) => {
+            journal.record(Operation { removed: before, ..Operation::new(session_key, &req.sender_id, OperationKind::Delete, &req.pic, &req.scope) });
+            String::from("delete ok: moved to trash")
+        }
         Err(t) => format!("delete fail: {}", t)
     };
 }
 
-fn handle_replace(req: &BotRequest) -> String {
+fn handle_replace(req: &BotRequest, session_key: &str, journal: &mut Journal, store: &mut dyn PicStore, images: &Option<ImageStore>) -> String {
     if req.pic.is_empty() {
         return String::from("replace fail: no pic");
     }
@@ -401,41 +747,1143 @@ fn handle_replace(req: &BotRequest) -> String {
         return String::from("replace fail: no text");
     }
 
-    let result = db::replace_word(&req.pic, &req.word, &req.db);
+    let before = match scope_tags(&req.pic, &req.scope, store) {
+        Ok(t) => t,
+        Err(t) => return format!("replace fail: {}", t)
+    };
+    let mut added: Vec<String> = vec!();
+    for word in req.word.split_whitespace() {
+        if !added.iter().any(|t| t == word) {
+            added.push(String::from(word));
+        }
+    }
+
+    let result = store.replace_word(&req.scope, &req.pic, &req.word, &req.tagger());
     return match result {
-        Ok(_) => String::from("replace ok"),
+        Ok(_) => {
+            journal.record(Operation { added, removed: before, ..Operation::new(session_key, &req.sender_id, OperationKind::Replace, &req.pic, &req.scope) });
+            format!("replace ok{}", dedup_pic_file(&req.pic, store, images))
+        }
         Err(t) => format!("replace fail: {}", t)
     };
 }
 
-fn handle_info(req: &BotRequest) -> String {
+// 图片在scope里的词，以及是谁加的
+fn scope_tags(pic: &str, scope: &str, store: &mut dyn PicStore) -> Result<Vec<TagRecord>, DbError> {
+    let mut tags = store.list_pic_tags(pic)?;
+    tags.retain(|t| t.scope == scope);
+    return Ok(tags);
+}
+
+fn handle_info(req: &BotRequest, store: &mut dyn PicStore) -> String {
     if req.pic.is_empty() {
         return String::from("info fail: no pic");
     }
 
-    let result = db::list_pic_words(&req.pic, &req.db);
-    return match result {
-        Ok(t) => format!("info ok: {}", t),
-        Err(t) => format!("info fail: {}", t)
+    let words = match store.list_pic_words(&req.pic) {
+        Ok(t) => t,
+        Err(t) => return format!("info fail: {}", t)
     };
+    let tags = match store.list_pic_tags(&req.pic) {
+        Ok(t) => t,
+        Err(t) => return format!("info fail: {}", t)
+    };
+
+    // 每个词一行：谁在哪里什么时候加的
+    let mut lines = vec!(format!("info ok: {}", words));
+    for tag in tags.iter() {
+        let tagger = if tag.tagger.user_id.is_empty() {
+            String::from("unknown")
+        } else if tag.tagger.group_id.is_empty() {
+            format!("{} in private", tag.tagger.user_id)
+        } else {
+            format!("{} in group {}", tag.tagger.user_id, tag.tagger.group_id)
+        };
+        let ts = match tag.created_ts {
+            Some(ref t) => format!(" at {}", t),
+            None => String::new()
+        };
+        lines.push(format!("{} by {}{} ({})", tag.word, tagger, ts, tag.scope));
+    }
+    return lines.join("\n");
 }
 
-fn handle_count(req: &BotRequest) -> String {
-    let result = db::count_pic(&req.db);
+fn handle_count(store: &mut dyn PicStore) -> String {
+    let result = store.count_pic();
     return match result {
         Ok(t) => format!("count ok: {}", t),
         Err(t) => format!("count fail: {}", t)
     };
 }
 
-fn handle_clean(req: &BotRequest) -> String {
-    let result = db::clean(&req.db);
-    return match result {
-        Ok(t) => format!("clean ok: {}", t),
-        Err(t) => format!("clean fail: {}", t)
+fn handle_clean(req: &BotRequest, session: &mut BotSession, store: &mut dyn PicStore, images: &Option<ImageStore>) -> String {
+    // 先列出来给管理员确认，确认之后才真正删除
+    if req.word == "confirm" {
+        let plan = session.clean_plan.take();
+        if plan.is_none() {
+            return String::from("clean fail: nothing to confirm, send clean first");
+        }
+        let plan = plan.unwrap();
+
+        if let Err(t) = store.remove_orphans(&plan.orphans) {
+            return format!("clean fail: {}", t);
+        }
+        let mut removed_files = 0;
+        if let Some(images) = images {
+            // 文件删除前也再确认一次没有被用上
+            let pics = match store.list_pics() {
+                Ok(t) => t,
+                Err(t) => return format!("clean fail: {}", t)
+            };
+            for file in plan.files.iter().filter(|t| !pics.contains(t)) {
+                match images.remove(file) {
+                    Ok(_) => removed_files += 1,
+                    Err(t) => println!("remove file fail: {}, {}", file, t)
+                }
+            }
+        }
+        return format!("clean ok: removed {} pics, {} words, {} broken links, {} files",
+                       plan.orphans.pics.len(), plan.orphans.words.len(), plan.orphans.pic_words, removed_files);
+    }
+
+    let orphans = match store.find_orphans() {
+        Ok(t) => t,
+        Err(t) => return format!("clean fail: {}", t)
+    };
+    let files = match images {
+        Some(images) => {
+            let pics = match store.list_pics() {
+                Ok(t) => t,
+                Err(t) => return format!("clean fail: {}", t)
+            };
+            match images.list() {
+                Ok(t) => t.into_iter().filter(|t| !pics.contains(t)).collect(),
+                Err(t) => return format!("clean fail: {}", t)
+            }
+        }
+        None => vec!()
+    };
+
+    if orphans.pics.is_empty() && orphans.words.is_empty() && orphans.pic_words == 0 && files.is_empty() {
+        session.clean_plan = None;
+        return String::from("clean ok: nothing to clean");
+    }
+
+    let text = format!("clean ok: {} pics without words, {} unused words, {} broken links, {} unreferenced files
+pics: {}
+words: {}
+files: {}
+send \"clean confirm\" to remove them",
+                       orphans.pics.len(), orphans.words.len(), orphans.pic_words, files.len(),
+                       preview(&orphans.pics), preview(&orphans.words), preview(&files));
+    session.clean_plan = Some(CleanPlan { orphans, files });
+    return text;
+
+    // 太多了就只列出前面一部分
+    fn preview(list: &[String]) -> String {
+        const MAX: usize = 20;
+        return if list.len() > MAX {
+            format!("{} ...", list[..MAX].join(" "))
+        } else {
+            list.join(" ")
+        };
+    }
+}
+
+fn handle_dedup(store: &mut dyn PicStore, images: &Option<ImageStore>) -> String {
+    let images = match images {
+        Some(t) => t,
+        None => return String::from("dedup fail: no pic_dir")
+    };
+
+    let pics = match store.list_unhashed_pics() {
+        Ok(t) => t,
+        Err(t) => return format!("dedup fail: {}", t)
     };
+    let mut merged = 0;
+    let mut missing = 0;
+    for pic in pics.iter() {
+        match images.save(pic) {
+            Ok(true) => {}
+            _ => {
+                missing += 1;
+                continue;
+            }
+        }
+        let hash = match images.hash(pic) {
+            Ok(Some(t)) => t,
+            Ok(None) => continue,
+            Err(t) => return format!("dedup fail: {}", t)
+        };
+        match store.dedup_pic(pic, &hash) {
+            Ok(Some(_)) => merged += 1,
+            Ok(None) => save_pic_phash(pic, store, images),
+            Err(t) => return format!("dedup fail: {}", t)
+        }
+    }
+    return format!("dedup ok: {} pics checked, {} merged, {} without file", pics.len(), merged, missing);
 }
 
-fn build_pic_output(pic: &str) -> String {
+fn handle_similar(req: &BotRequest, store: &mut dyn PicStore, images: &Option<ImageStore>) -> String {
+    let images = match images {
+        Some(t) => t,
+        None => return String::from("similar fail: no pic_dir")
+    };
+
+    let mut args = req.word.split_whitespace().peekable();
+    let merge = args.peek() == Some(&"merge");
+    if merge {
+        args.next();
+    }
+    let threshold = match args.next() {
+        Some(t) => match t.trim_end_matches('%').parse::<u32>() {
+            Ok(t) if t <= 100 => t,
+            _ => return format!("similar fail: bad threshold: {}", t)
+        },
+        None => 90
+    };
+
+    let clusters = match find_similar_pics(threshold, store, images) {
+        Ok(t) => t,
+        Err(t) => return format!("similar fail: {}", t)
+    };
+    if clusters.is_empty() {
+        return format!("similar ok: no similar pics above {}%", threshold);
+    }
+
+    if merge {
+        let mut merged = 0;
+        for cluster in clusters.iter() {
+            // 都合并到每组的第一张图片上
+            for pic in cluster[1..].iter() {
+                if let Err(t) = store.merge_pic(pic, &cluster[0]) {
+                    return format!("similar fail: {}", t);
+                }
+                merged += 1;
+            }
+        }
+        return format!("similar ok: merged {} pics in {} groups", merged, clusters.len());
+    }
+
+    let lines: Vec<String> = clusters.iter().map(|t| t.join(" ")).collect();
+    return format!("similar ok: {} groups above {}%
+{}
+send \"similar merge {}\" to merge them", clusters.len(), threshold, lines.join("\n"), threshold);
+}
+
+fn handle_synonym(req: &BotRequest, store: &mut dyn PicStore) -> String {
+    let mut args = req.word.split_whitespace();
+    let action = args.next().unwrap_or("");
+    let words: Vec<&str> = args.collect();
+
+    return match action {
+        "add" => {
+            if words.len() < 2 {
+                return String::from("synonym fail: need at least 2 words");
+            }
+            match store.add_synonyms(&words) {
+                Ok(_) => format!("synonym ok: added {}", words.join(" ")),
+                Err(t) => format!("synonym fail: {}", t)
+            }
+        }
+        "remove" => {
+            if words.is_empty() {
+                return String::from("synonym fail: no word");
+            }
+            let mut removed = vec!();
+            for word in words {
+                match store.remove_synonym(word) {
+                    Ok(true) => removed.push(word),
+                    Ok(false) => {}
+                    Err(t) => return format!("synonym fail: {}", t)
+                }
+            }
+            format!("synonym ok: removed {}", removed.join(" "))
+        }
+        "list" => match store.list_synonyms() {
+            Ok(t) => if t.is_empty() {
+                String::from("synonym ok: no synonyms")
+            } else {
+                let lines: Vec<String> = t.iter().map(|t| t.join(" ")).collect();
+                format!("synonym ok: {} groups\n{}", t.len(), lines.join("\n"))
+            },
+            Err(t) => format!("synonym fail: {}", t)
+        },
+        _ => String::from("synonym fail: use synonym add/remove/list")
+    };
+}
+
+fn handle_scope(req: &BotRequest, session: &mut BotSession, store: &mut dyn PicStore) -> String {
+    let args: Vec<&str> = req.word.split_whitespace().collect();
+    let scope = match args.as_slice() {
+        [] => req.scope.clone(),
+        ["global"] => String::from(db::GLOBAL_SCOPE),
+        ["group", id] => db::group_scope(id),
+        ["user", id] => db::user_scope(id),
+        ["reset"] => db::user_scope(&req.sender_id),
+        ["search_global", on] => {
+            let on = match *on {
+                "on" => true,
+                "off" => false,
+                _ => return format!("scope fail: bad value: {}", on)
+            };
+            return match store.set_search_global(&req.scope, on) {
+                Ok(_) => format!("scope ok: {} search global {}", req.scope, if on { "on" } else { "off" }),
+                Err(t) => format!("scope fail: {}", t)
+            };
+        }
+        _ => return String::from("scope fail: use scope global / group 群号 / user qq号 / reset / search_global on|off")
+    };
+
+    let search_global = match store.search_global(&scope) {
+        Ok(t) => t,
+        Err(t) => return format!("scope fail: {}", t)
+    };
+    if !args.is_empty() {
+        session.scope = Some(scope.clone());
+    }
+    return format!("scope ok: {}, search global {}", scope, if search_global { "on" } else { "off" });
+}
+
+fn handle_undo(req: &BotRequest, session_key: &str, is_admin: bool, journal: &mut Journal, store: &mut dyn PicStore) -> String {
+    // 管理员可以列出所有会话里记下来的操作，撤销其中任意一次
+    let op = match req.word.as_str() {
+        "" => journal.last(session_key, &req.sender_id),
+        "list" if is_admin => {
+            let list = journal.list();
+            if list.is_empty() {
+                return String::from("undo ok: nothing to undo");
+            }
+            let lines: Vec<String> = list.iter().map(|t| t.describe()).collect();
+            return format!("undo ok: {} operations\n{}", list.len(), lines.join("\n"));
+        }
+        _ if is_admin => match req.word.trim_start_matches('#').parse() {
+            Ok(t) => journal.get(t),
+            Err(_) => return String::from("undo fail: use undo / undo list / undo 编号")
+        },
+        _ => return String::from("undo fail: only admin can undo others")
+    };
+    let op = match op {
+        Some(t) => t,
+        None => return String::from("undo fail: nothing to undo")
+    };
+
+    if let Err(t) = op.undo(store) {
+        return format!("undo fail: {}", t);
+    }
+    let (id, text) = (op.id, op.describe());
+    journal.remove(id);
+    return format!("undo ok: {}", text);
+}
+
+fn handle_trash(store: &mut dyn PicStore) -> String {
+    return match store.list_trash() {
+        Ok(t) => if t.is_empty() {
+            String::from("trash ok: trash is empty")
+        } else {
+            let lines: Vec<String> = t.iter().map(|t| format!("{} at {}", t.0, t.1)).collect();
+            format!("trash ok: {} pics\n{}", t.len(), lines.join("\n"))
+        },
+        Err(t) => format!("trash fail: {}", t)
+    };
+}
+
+fn handle_restore(req: &BotRequest, store: &mut dyn PicStore) -> String {
+    // 回收站里的图片也可以直接用trash列出来的名字
+    let pic = if req.word.is_empty() { req.pic.as_str() } else { req.word.as_str() };
+    if pic.is_empty() {
+        return String::from("restore fail: no pic");
+    }
+
+    return match store.restore_pic(pic) {
+        Ok(true) => format!("restore ok: {}", pic),
+        Ok(false) => format!("restore fail: {} is not in trash", pic),
+        Err(t) => format!("restore fail: {}", t)
+    };
+}
+
+fn handle_purge(req: &BotRequest, store: &mut dyn PicStore) -> String {
+    // 图片文件留给clean去删
+    if req.word == "all" {
+        return match store.purge_trash(0) {
+            Ok(t) => format!("purge ok: {} pics", t.len()),
+            Err(t) => format!("purge fail: {}", t)
+        };
+    }
+
+    let pic = if req.word.is_empty() { req.pic.as_str() } else { req.word.as_str() };
+    if pic.is_empty() {
+        return String::from("purge fail: no pic");
+    }
+    return match store.purge_pic(pic) {
+        Ok(true) => format!("purge ok: {}", pic),
+        Ok(false) => format!("purge fail: {} is not in trash", pic),
+        Err(t) => format!("purge fail: {}", t)
+    };
+}
+
+fn handle_dict(req: &BotRequest, store: &mut dyn PicStore, segmenter: &mut Option<Segmenter>) -> String {
+    let mut args = req.word.split_whitespace();
+    let action = args.next().unwrap_or("");
+    let words: Vec<&str> = args.collect();
+
+    return match action {
+        "add" => {
+            if words.is_empty() {
+                return String::from("dict fail: no word");
+            }
+            if let Err(t) = store.add_dict_words(&words) {
+                return format!("dict fail: {}", t);
+            }
+            if let Some(ref mut t) = segmenter {
+                for word in words.iter() {
+                    t.add_word(word);
+                }
+            }
+            format!("dict ok: added {}", words.join(" "))
+        }
+        "remove" => {
+            if words.is_empty() {
+                return String::from("dict fail: no word");
+            }
+            let mut removed = vec!();
+            for word in words.iter() {
+                match store.remove_dict_word(word) {
+                    Ok(true) => removed.push(*word),
+                    Ok(false) => {}
+                    Err(t) => return format!("dict fail: {}", t)
+                }
+            }
+            if removed.is_empty() {
+                return String::from("dict fail: no such word");
+            }
+            if let Some(ref mut t) = segmenter {
+                if let Err(t) = t.reload(store) {
+                    return format!("dict fail: {}", t);
+                }
+            }
+            format!("dict ok: removed {}", removed.join(" "))
+        }
+        "" | "list" => match store.list_dict_words() {
+            Ok(t) => if t.is_empty() {
+                String::from("dict ok: no words")
+            } else {
+                format!("dict ok: {} words\n{}", t.len(), t.join(" "))
+            },
+            Err(t) => format!("dict fail: {}", t)
+        },
+        _ => String::from("dict fail: use dict / dict add 词1 词2 / dict remove 词")
+    };
+}
+
+fn handle_backup(req: &BotRequest, store: &mut dyn PicStore, backup: &Option<Arc<Mutex<Backup>>>) -> String {
+    let mut backup = match backup {
+        Some(t) => match t.lock() {
+            Ok(t) => t,
+            Err(_) => return String::from("backup fail: backup thread panicked")
+        },
+        None => return String::from("backup fail: no backup_dir")
+    };
+
+    if req.word == "list" {
+        return match backup.list() {
+            Ok(t) => if t.is_empty() {
+                String::from("backup ok: no backups")
+            } else {
+                let lines: Vec<String> = t.iter().map(|t| t.0.clone()).collect();
+                format!("backup ok: {} backups\n{}", t.len(), lines.join("\n"))
+            },
+            Err(t) => format!("backup fail: {}", t)
+        };
+    }
+
+    return match backup.run(store) {
+        Ok(t) => format!("backup ok: {} {} records", t.0, t.1),
+        Err(t) => format!("backup fail: {}", t)
+    };
+}
+
+// 感知hash的相似度不低于threshold%的图片分到一组，只返回不止一张图片的组
+fn find_similar_pics(threshold: u32, store: &mut dyn PicStore, images: &ImageStore) -> Result<Vec<Vec<String>>, DbError> {
+    let mut pics = vec!();
+    for (pic, phash) in store.list_pic_phashes()? {
+        let phash = match phash {
+            Some(t) => Some(t),
+            // 以前保存的图片还没算过，顺便补上
+            None => if let Ok(true) = images.save(&pic) {
+                let t = images.phash(&pic)?;
+                if let Some(t) = t {
+                    store.set_pic_phash(&pic, t)?;
+                }
+                t
+            } else {
+                None
+            }
+        };
+        if let Some(t) = phash {
+            pics.push((pic, t));
+        }
+    }
+
+    // 64位里允许不同的位数
+    let max_distance = (100 - threshold) * 64 / 100;
+
+    let distance = |a: usize, b: usize| (pics[a].1 ^ pics[b].1).count_ones();
+
+    // 全链接聚类，两组里任意两张图片都足够相似才能合成一组，每次先合并最像的两组
+    // a像b、b像c时不会把不像的a和c分到一起
+    let mut clusters: Vec<Vec<usize>> = (0..pics.len())
+        .filter(|&i| (0..pics.len()).any(|j| j != i && distance(i, j) <= max_distance))
+        .map(|i| vec!(i))
+        .collect();
+    loop {
+        let mut best: Option<(u32, usize, usize)> = None;
+        for a in 0..clusters.len() {
+            for b in a + 1..clusters.len() {
+                let d = clusters[a].iter()
+                    .flat_map(|&i| clusters[b].iter().map(move |&j| distance(i, j)))
+                    .max()
+                    .unwrap_or(0);
+                if d <= max_distance && !best.iter().any(|t| t.0 <= d) {
+                    best = Some((d, a, b));
+                }
+            }
+        }
+        let (a, b) = match best {
+            Some(t) => (t.1, t.2),
+            None => break
+        };
+        let t = clusters.remove(b);
+        clusters[a].extend(t);
+    }
+
+    let mut clusters: Vec<Vec<usize>> = clusters.into_iter().filter(|t| t.len() > 1).collect();
+    for t in clusters.iter_mut() {
+        t.sort();
+    }
+    clusters.sort();
+    return Ok(clusters.iter().map(|t| t.iter().map(|&i| pics[i].0.clone()).collect()).collect());
+}
+
+// 查询时要查的范围：自己的范围，以及设置了的话再加上全局图库
+fn search_scopes(scope: &str, store: &mut dyn PicStore) -> Result<Vec<String>, DbError> {
+    let mut scopes = vec!(String::from(scope));
+    if scope != db::GLOBAL_SCOPE && store.search_global(scope)? {
+        scopes.push(String::from(db::GLOBAL_SCOPE));
+    }
+    return Ok(scopes);
+}
+
+// 用了倒排索引的话在索引里查，否则在数据库里查
+fn find_pics(scopes: &[String], query: &WordQuery, rank: Rank, store: &mut dyn PicStore, search: &mut Option<SearchIndex>) -> Result<Vec<String>, DbError> {
+    return match search {
+        Some(t) => t.query_pic(scopes, query, rank, store),
+        None => store.query_pic(scopes, query, rank)
+    };
+}
+
+// 查到的图片发出去，并记录这些图片用过了
+fn build_query_output(pics: &[String], scopes: &[String], query: &WordQuery, req: &BotRequest, store: &mut dyn PicStore, images: &Option<ImageStore>,
+                      search: &mut Option<SearchIndex>) -> Vec<BotResponse> {
+    return pics.iter()
+        .map(|pic| {
+            let text = build_pic_output(pic, images);
+            for word in query.words() {
+                if let Err(t) = store.touch_pic(scopes, pic, word) {
+                    println!("touch pic fail: {}, {}", pic, t);
+                }
+                if let Some(ref mut t) = search {
+                    t.touch_pic(scopes, pic, word);
+                }
+            }
+            BotResponse::new(text, req)
+        })
+        .collect();
+}
+
+// 把图片文件保存到自己的图片目录里，返回文件内容的hash以及附加在回复后面的提示
+fn save_pic_file(pic: &str, images: &Option<ImageStore>) -> (Option<String>, String) {
+    let images = match images {
+        Some(t) => t,
+        None => return (None, String::new())
+    };
+
+    match images.save(pic) {
+        Ok(true) => {}
+        Ok(false) => return (None, String::from(", but pic file not found")),
+        Err(t) => return (None, format!(", but save pic file fail: {}", t))
+    }
+    return match images.hash(pic) {
+        Ok(t) => (t, String::new()),
+        Err(t) => (None, format!(", but dedup fail: {}", t))
+    };
+}
+
+// 保存图片文件，已经有内容相同的图片时把这张合并过去，返回附加在回复后面的提示
+fn dedup_pic_file(pic: &str, store: &mut dyn PicStore, images: &Option<ImageStore>) -> String {
+    let (hash, saved) = save_pic_file(pic, images);
+    let hash = match hash {
+        Some(t) => t,
+        None => return saved
+    };
+    return match store.dedup_pic(pic, &hash) {
+        Ok(Some(t)) => format!(", same pic as {}", t),
+        Ok(None) => {
+            if let Some(t) = images {
+                save_pic_phash(pic, store, t);
+            }
+            String::new()
+        }
+        Err(t) => format!(", but dedup fail: {}", t)
+    };
+}
+
+// 感知hash只是similar用的缓存，这里没存上的话similar时会再补上
+fn save_pic_phash(pic: &str, store: &mut dyn PicStore, images: &ImageStore) {
+    if let Ok(Some(t)) = images.phash(pic) {
+        store.set_pic_phash(pic, t).unwrap_or(());
+    }
+}
+
+fn build_pic_output(pic: &str, images: &Option<ImageStore>) -> String {
+    if let Some(images) = images {
+        match images.fetch(pic) {
+            Ok(true) => {}
+            Ok(false) => return format!("pic missing: {}", pic),
+            Err(t) => return format!("pic missing: {}, {}", pic, t)
+        }
+    }
     return format!("{}{}{}", PIC_START, pic, PIC_END);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::MemoryStore;
+    use std::path::PathBuf;
+    use std::{env, fs, process, thread};
+
+    const ADMIN: &str = "10000";
+    const USER: &str = "20000";
+    const GROUP: &str = "30000";
+    const BOT: &str = "99999";
+
+    fn globals() -> BotGlobals {
+        return BotGlobals::new(String::from(ADMIN), Box::new(MemoryStore::new()), None, 30, None, None, None);
+    }
+
+    // 管理员私聊发的消息
+    fn send(globals: &mut BotGlobals, message: &str) -> Vec<String> {
+        return send_from(globals, ADMIN, "", message);
+    }
+
+    // 群里@tutu发的消息
+    fn send_group(globals: &mut BotGlobals, sender: &str, group: &str, message: &str) -> Vec<String> {
+        return send_from(globals, sender, group, &format!("[@{}] {}", BOT, message));
+    }
+
+    fn send_from(globals: &mut BotGlobals, sender: &str, group: &str, message: &str) -> Vec<String> {
+        let mut params = HashMap::new();
+        params.insert(String::from("Message"), String::from(message));
+        params.insert(String::from("QQ"), String::from(sender));
+        params.insert(String::from("ExternalId"), String::from(group));
+        params.insert(String::from("RobotQQ"), String::from(BOT));
+        params.insert(String::from("Name"), String::from("tutu"));
+
+        let mut req = BotRequest::new(&params, globals);
+        return process_request(&mut req, globals).into_iter().map(|t| t.text).collect();
+    }
+
+    // 只关心第一条回复
+    fn reply(globals: &mut BotGlobals, message: &str) -> String {
+        return send(globals, message).into_iter().next().unwrap_or_default();
+    }
+
+    // 测试是并行跑的，每个测试用自己的目录
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tutuv3-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    #[test]
+    fn help_and_about() {
+        let mut globals = globals();
+        assert!(reply(&mut globals, "help").starts_with("tutu bot admin commands"));
+        assert!(send_group(&mut globals, USER, GROUP, "help")[0].starts_with("tutu bot v3.0"));
+        assert!(reply(&mut globals, "about").starts_with("about tutu"));
+    }
+
+    #[test]
+    fn ignore_self_and_others() {
+        let mut globals = globals();
+        assert!(send_from(&mut globals, BOT, "", "help").is_empty());
+        // 非管理员的私聊和群里没有@tutu的消息都不理
+        assert!(send_from(&mut globals, USER, "", "help").is_empty());
+        assert!(send_from(&mut globals, USER, GROUP, "help").is_empty());
+        // 管理员命令在群里当成查询
+        assert_eq!(send_group(&mut globals, USER, GROUP, "count"), vec!("query fail: not found"));
+    }
+
+    #[test]
+    fn set_and_query() {
+        let mut globals = globals();
+        assert_eq!(reply(&mut globals, "set [图片=a/] 猫 狗"), "set ok");
+        assert_eq!(reply(&mut globals, "set [图片=b/] 猫"), "set ok");
+        assert_eq!(reply(&mut globals, "set"), "set fail: no pic");
+        assert_eq!(reply(&mut globals, "set [图片=c/]"), "set fail: no text");
+
+        assert_eq!(send(&mut globals, "狗"), vec!("[图片=a/]"));
+        let mut pics = send(&mut globals, "猫");
+        pics.sort();
+        assert_eq!(pics, vec!("[图片=a/]", "[图片=b/]"));
+        assert_eq!(send(&mut globals, "兔子"), vec!("query fail: not found"));
+    }
+
+    #[test]
+    fn multi_word_query() {
+        let mut globals = globals();
+        reply(&mut globals, "set [图片=a/] 猫 狗");
+        reply(&mut globals, "set [图片=b/] 猫 鱼");
+
+        assert_eq!(send(&mut globals, "猫 鱼"), vec!("[图片=b/]"));
+        assert_eq!(send(&mut globals, "猫 -狗"), vec!("[图片=b/]"));
+        assert_eq!(send(&mut globals, "狗|鱼").len(), 2);
+        assert_eq!(send(&mut globals, "狗 鱼"), vec!("query fail: not found"));
+        assert_eq!(send(&mut globals, "-猫"), vec!("query fail: no text"));
+    }
+
+    #[test]
+    fn query_fuzzy() {
+        let mut globals = globals();
+        reply(&mut globals, "set [图片=a/] 猫咪");
+        reply(&mut globals, "set [图片=b/] 小狗狗");
+        assert_eq!(send(&mut globals, "猫"), vec!("[图片=a/]", "did you mean: 猫咪"));
+        assert_eq!(send(&mut globals, "大狗狗"), vec!("[图片=b/]", "did you mean: 小狗狗"));
+        assert_eq!(send(&mut globals, "猫咪 鱼"), vec!("query fail: not found"));
+        assert_eq!(send(&mut globals, "猫咪 狗"), vec!("query fail: not found, did you mean: 小狗狗"));
+    }
+
+    #[test]
+    fn query_forms() {
+        let mut globals = globals();
+        reply(&mut globals, "set [图片=a/] 猫咪");
+        assert_eq!(send(&mut globals, "maomi"), vec!("[图片=a/]"));
+        assert_eq!(send(&mut globals, "MM"), vec!("[图片=a/]"));
+        assert_eq!(send(&mut globals, "貓咪"), vec!("[图片=a/]"));
+        assert_eq!(send(&mut globals, "猫咪 -mm"), vec!("query fail: not found"));
+    }
+
+    #[test]
+    fn set_previous_pic() {
+        let mut globals = globals();
+        assert!(send(&mut globals, "[图片=a/]").is_empty());
+        assert_eq!(reply(&mut globals, "set 猫"), "set ok");
+        assert_eq!(send(&mut globals, "猫"), vec!("[图片=a/]"));
+
+        // 群里没有@tutu的图片也记下来
+        assert!(send_from(&mut globals, USER, GROUP, "[图片=b/]").is_empty());
+        assert_eq!(send_group(&mut globals, USER, GROUP, "set 狗"), vec!("set ok"));
+        assert_eq!(send_group(&mut globals, USER, GROUP, "狗"), vec!("[图片=b/]"));
+    }
+
+    #[test]
+    fn random() {
+        let mut globals = globals();
+        assert_eq!(reply(&mut globals, "random"), "random fail: db empty");
+        reply(&mut globals, "set [图片=a/] 猫");
+        assert_eq!(reply(&mut globals, "random"), "[图片=a/]");
+    }
+
+    #[test]
+    fn replace_and_info() {
+        let mut globals = globals();
+        reply(&mut globals, "set [图片=a/] 猫 狗");
+        assert!(reply(&mut globals, "info [图片=a/]").starts_with("info ok: 猫 狗\n"));
+        assert_eq!(reply(&mut globals, "replace [图片=a/] 鱼"), "replace ok");
+        assert_eq!(reply(&mut globals, "replace [图片=a/]"), "replace fail: no text");
+        assert_eq!(send(&mut globals, "猫"), vec!("query fail: not found"));
+        assert!(reply(&mut globals, "info [图片=a/]").starts_with("info ok: 鱼\n"));
+        assert_eq!(reply(&mut globals, "info"), "info fail: no pic");
+    }
+
+    #[test]
+    fn delete_and_count() {
+        let mut globals = globals();
+        assert_eq!(reply(&mut globals, "count"), "count ok: 0");
+        reply(&mut globals, "set [图片=a/] 猫");
+        reply(&mut globals, "set [图片=b/] 狗");
+        assert_eq!(reply(&mut globals, "count"), "count ok: 2");
+
+        assert_eq!(reply(&mut globals, "delete"), "delete fail: no pic");
+        assert_eq!(reply(&mut globals, "delete [图片=a/]"), "delete ok: moved to trash");
+        assert_eq!(send(&mut globals, "猫"), vec!("query fail: not found"));
+        assert_eq!(reply(&mut globals, "count"), "count ok: 1");
+    }
+
+    #[test]
+    fn clean() {
+        let mut globals = globals();
+        assert_eq!(reply(&mut globals, "clean confirm"), "clean fail: nothing to confirm, send clean first");
+        reply(&mut globals, "set [图片=a/] 猫");
+        assert_eq!(reply(&mut globals, "clean"), "clean ok: nothing to clean");
+
+        reply(&mut globals, "replace [图片=a/] 狗");
+        assert!(reply(&mut globals, "clean").starts_with("clean ok: 0 pics without words, 1 unused words, 0 broken links, 0 unreferenced files\n"));
+        assert_eq!(reply(&mut globals, "clean confirm"), "clean ok: removed 0 pics, 1 words, 0 broken links, 0 files");
+        assert_eq!(reply(&mut globals, "clean"), "clean ok: nothing to clean");
+        assert_eq!(send(&mut globals, "狗"), vec!("[图片=a/]"));
+    }
+
+    #[test]
+    fn clean_files() {
+        let dir = temp_dir("clean");
+        fs::write(dir.join("a"), b"a").unwrap();
+        fs::write(dir.join("b"), b"b").unwrap();
+        let mut globals = globals();
+        globals.images = Some(ImageStore::new(dir.to_str().unwrap(), ""));
+
+        reply(&mut globals, "set [图片=a/] 猫");
+        assert!(reply(&mut globals, "clean").contains("1 unreferenced files\npics: \nwords: \nfiles: b\n"));
+        // 确认之前又用上了的文件不删
+        reply(&mut globals, "set [图片=b/] 狗");
+        assert_eq!(reply(&mut globals, "clean confirm"), "clean ok: removed 0 pics, 0 words, 0 broken links, 0 files");
+        assert!(dir.join("b").is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pic_files() {
+        let dir = temp_dir("pic-files");
+        let client = temp_dir("pic-files-client");
+        fs::write(client.join("a.png"), b"a").unwrap();
+        let mut globals = globals();
+        globals.images = Some(ImageStore::new(dir.to_str().unwrap(), client.to_str().unwrap()));
+
+        assert_eq!(reply(&mut globals, "set [图片=a.png/] 猫"), "set ok");
+        assert!(dir.join("a.png").is_file());
+        assert_eq!(reply(&mut globals, "set [图片=b.png/] 狗"), "set ok, but pic file not found");
+
+        // 客户端清了缓存之后从自己的目录恢复过去
+        fs::remove_file(client.join("a.png")).unwrap();
+        assert_eq!(send(&mut globals, "猫"), vec!("[图片=a.png/]"));
+        assert!(client.join("a.png").is_file());
+        assert_eq!(send(&mut globals, "狗"), vec!("pic missing: b.png"));
+        assert!(reply(&mut globals, "set [图片=../x/] 猫").starts_with("set ok, but save pic file fail: bad pic name"));
+
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&client).unwrap();
+    }
+
+    #[test]
+    fn dedup() {
+        let dir = temp_dir("dedup");
+        let client = temp_dir("dedup-client");
+        fs::write(client.join("a.png"), b"same").unwrap();
+        fs::write(client.join("b.png"), b"same").unwrap();
+        fs::write(client.join("c.png"), b"same").unwrap();
+        let mut globals = globals();
+        assert_eq!(reply(&mut globals, "dedup"), "dedup fail: no pic_dir");
+        reply(&mut globals, "set [图片=c.png/] 鱼");
+        globals.images = Some(ImageStore::new(dir.to_str().unwrap(), client.to_str().unwrap()));
+
+        assert_eq!(reply(&mut globals, "set [图片=a.png/] 猫"), "set ok");
+        assert_eq!(reply(&mut globals, "set [图片=b.png/] 狗"), "set ok, same pic as a.png");
+        assert_eq!(send(&mut globals, "狗"), vec!("[图片=a.png/]"));
+        // 被合并掉的图片名还能用
+        assert!(reply(&mut globals, "info [图片=b.png/]").starts_with("info ok: 猫 狗\n"));
+
+        // 之前没有计算过hash的图片
+        assert_eq!(reply(&mut globals, "dedup"), "dedup ok: 1 pics checked, 1 merged, 0 without file");
+        assert_eq!(send(&mut globals, "鱼"), vec!("[图片=a.png/]"));
+        assert_eq!(reply(&mut globals, "count"), "count ok: 1");
+
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&client).unwrap();
+    }
+
+
+    #[test]
+    fn similar() {
+        let dir = temp_dir("similar");
+        let mut globals = globals();
+        assert_eq!(reply(&mut globals, "similar"), "similar fail: no pic_dir");
+        for (pic, word) in [("a", "猫"), ("b", "狗"), ("c", "鱼"), ("d", "鸟")].iter() {
+            reply(&mut globals, &format!("set [图片={}/] {}", pic, word));
+        }
+        globals.images = Some(ImageStore::new(dir.to_str().unwrap(), ""));
+
+        // a和b、b和c都只差6位，a和c差12位，90%时最多允许差6位
+        globals.store.set_pic_phash("a", 0).unwrap();
+        globals.store.set_pic_phash("b", 0x3f).unwrap();
+        globals.store.set_pic_phash("c", 0xfff).unwrap();
+        globals.store.set_pic_phash("d", u64::MAX).unwrap();
+        assert_eq!(reply(&mut globals, "similar"), "similar ok: 1 groups above 90%\na b\nsend \"similar merge 90\" to merge them");
+        assert_eq!(reply(&mut globals, "similar 80"), "similar ok: 1 groups above 80%\na b c\nsend \"similar merge 80\" to merge them");
+        assert_eq!(reply(&mut globals, "similar 101"), "similar fail: bad threshold: 101");
+
+        assert_eq!(reply(&mut globals, "similar merge"), "similar ok: merged 1 pics in 1 groups");
+        assert!(reply(&mut globals, "info [图片=b/]").starts_with("info ok: 猫 狗\n"));
+        assert_eq!(reply(&mut globals, "similar"), "similar ok: no similar pics above 90%");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+
+    #[test]
+    fn rank() {
+        let mut globals = globals();
+        reply(&mut globals, "set [图片=a/] 猫");
+        reply(&mut globals, "set [图片=b/] 猫");
+        // 发出去的两张图片都会刷新last_ts，先发的那张先刷新
+        assert_eq!(send(&mut globals, "猫"), vec!("[图片=b/]", "[图片=a/]"));
+        assert_eq!(send(&mut globals, "!new 猫"), vec!("[图片=a/]", "[图片=b/]"));
+        assert_eq!(send(&mut globals, "!old 猫")[0], "[图片=a/]");
+
+        // 刚set的c虽然最新，但还没发过
+        reply(&mut globals, "set [图片=c/] 猫");
+        assert_eq!(send(&mut globals, "!hot 猫")[0], "[图片=b/]");
+        assert_eq!(send(&mut globals, "!hot"), vec!("query fail: no text"));
+    }
+
+
+    #[test]
+    fn synonym() {
+        let mut globals = globals();
+        reply(&mut globals, "set [图片=a/] 猫");
+        assert_eq!(reply(&mut globals, "synonym add 猫"), "synonym fail: need at least 2 words");
+        assert_eq!(reply(&mut globals, "synonym add 猫 喵"), "synonym ok: added 猫 喵");
+        assert_eq!(send(&mut globals, "喵"), vec!("[图片=a/]"));
+
+        // 与已有的组有重叠时并到一组
+        assert_eq!(reply(&mut globals, "synonym add 喵 咪"), "synonym ok: added 喵 咪");
+        assert_eq!(reply(&mut globals, "synonym list"), "synonym ok: 1 groups\n猫 喵 咪");
+        assert_eq!(send(&mut globals, "咪 -喵"), vec!("query fail: not found"));
+
+        assert_eq!(reply(&mut globals, "synonym remove 喵 狗"), "synonym ok: removed 喵");
+        assert_eq!(send(&mut globals, "喵"), vec!("query fail: not found"));
+        assert_eq!(send(&mut globals, "咪"), vec!("[图片=a/]"));
+        reply(&mut globals, "synonym remove 咪");
+        assert_eq!(reply(&mut globals, "synonym list"), "synonym ok: no synonyms");
+        assert!(reply(&mut globals, "synonym foo").starts_with("synonym fail: use "));
+    }
+
+
+    #[test]
+    fn group_scope() {
+        let mut globals = globals();
+        assert_eq!(send_group(&mut globals, USER, GROUP, "set [图片=a/] 猫"), vec!("set ok"));
+        assert_eq!(send_group(&mut globals, USER, GROUP, "猫"), vec!("[图片=a/]"));
+        assert_eq!(send_group(&mut globals, USER, "30001", "猫"), vec!("query fail: not found"));
+        assert_eq!(send(&mut globals, "猫"), vec!("query fail: not found"));
+        assert_eq!(send_group(&mut globals, USER, GROUP, "random"), vec!("[图片=a/]"));
+        assert_eq!(send_group(&mut globals, USER, "30001", "random"), vec!("random fail: db empty"));
+    }
+
+    #[test]
+    fn scope() {
+        let mut globals = globals();
+        assert_eq!(reply(&mut globals, "scope"), format!("scope ok: u:{}, search global on", ADMIN));
+        assert_eq!(reply(&mut globals, "scope global"), "scope ok: global, search global on");
+        reply(&mut globals, "set [图片=a/] 猫");
+
+        // 全局图库里的图片哪里都能查到，除非关掉了
+        assert_eq!(send_group(&mut globals, USER, GROUP, "猫"), vec!("[图片=a/]"));
+        reply(&mut globals, &format!("scope group {}", GROUP));
+        assert_eq!(reply(&mut globals, "scope search_global off"), format!("scope ok: g:{} search global off", GROUP));
+        assert_eq!(send_group(&mut globals, USER, GROUP, "猫"), vec!("query fail: not found"));
+        assert!(reply(&mut globals, "scope search_global maybe").starts_with("scope fail: bad value"));
+
+        assert_eq!(reply(&mut globals, "scope reset"), format!("scope ok: u:{}, search global on", ADMIN));
+        assert_eq!(send(&mut globals, "猫"), vec!("[图片=a/]"));
+        assert!(reply(&mut globals, "scope foo").starts_with("scope fail: use "));
+    }
+
+
+    #[test]
+    fn info_tagger() {
+        let mut globals = globals();
+        send_group(&mut globals, USER, GROUP, "set [图片=a/] 猫");
+        reply(&mut globals, "set [图片=a/] 狗");
+        let info = reply(&mut globals, "info [图片=a/]");
+        let lines: Vec<&str> = info.lines().collect();
+        assert_eq!(lines[0], "info ok: 猫 狗");
+        assert!(lines[1].starts_with(&format!("猫 by {} in group {} at ", USER, GROUP)));
+        assert!(lines[1].ends_with(&format!("(g:{})", GROUP)));
+        assert!(lines[2].starts_with(&format!("狗 by {} in private at ", ADMIN)));
+        assert!(lines[2].ends_with(&format!("(u:{})", ADMIN)));
+    }
+
+
+    #[test]
+    fn undo() {
+        let mut globals = globals();
+        assert_eq!(send_group(&mut globals, USER, GROUP, "undo"), vec!("undo fail: nothing to undo"));
+
+        send_group(&mut globals, USER, GROUP, "set [图片=a/] 猫");
+        send_group(&mut globals, USER, GROUP, "set [图片=a/] 猫 狗");
+        assert_eq!(send_group(&mut globals, USER, GROUP, "undo")[0], format!("undo ok: #2 set by {} in g{} on a +狗", USER, GROUP));
+        assert_eq!(send_group(&mut globals, USER, GROUP, "狗"), vec!("query fail: not found"));
+        assert_eq!(send_group(&mut globals, USER, GROUP, "猫"), vec!("[图片=a/]"));
+
+        // 别人的操作只有管理员能撤销
+        assert_eq!(send_group(&mut globals, "20001", GROUP, "undo 1"), vec!("undo fail: only admin can undo others"));
+        assert!(reply(&mut globals, "undo list").starts_with("undo ok: 1 operations\n#1 set"));
+        assert!(reply(&mut globals, "undo 1").starts_with("undo ok: #1 set"));
+        assert_eq!(send_group(&mut globals, USER, GROUP, "猫"), vec!("query fail: not found"));
+        assert_eq!(reply(&mut globals, "undo 1"), "undo fail: nothing to undo");
+    }
+
+    #[test]
+    fn undo_replace_delete() {
+        let mut globals = globals();
+        reply(&mut globals, "set [图片=a/] 猫 狗");
+
+        reply(&mut globals, "replace [图片=a/] 鱼");
+        assert!(reply(&mut globals, "undo").starts_with("undo ok: #2 replace"));
+        assert_eq!(send(&mut globals, "猫 狗"), vec!("[图片=a/]"));
+        assert_eq!(send(&mut globals, "鱼"), vec!("query fail: not found"));
+
+        reply(&mut globals, "delete [图片=a/]");
+        assert!(reply(&mut globals, "undo").starts_with("undo ok: #3 delete"));
+        assert_eq!(send(&mut globals, "猫"), vec!("[图片=a/]"));
+    }
+
+    #[test]
+    fn undo_set_merged_into_same_pic() {
+        let dir = temp_dir("undo-merged");
+        let client = temp_dir("undo-merged-client");
+        fs::write(client.join("a.png"), b"same").unwrap();
+        fs::write(client.join("a_copy.png"), b"same").unwrap();
+        let mut globals = globals();
+        globals.images = Some(ImageStore::new(dir.to_str().unwrap(), client.to_str().unwrap()));
+
+        // a.png上原来就有的甲撤销时不能去掉
+        assert_eq!(reply(&mut globals, "set [图片=a.png/] 甲"), "set ok");
+        assert_eq!(reply(&mut globals, "set [图片=a_copy.png/] 甲 乙"), "set ok, same pic as a.png");
+        assert!(reply(&mut globals, "undo").ends_with("on a.png +乙"));
+        assert!(reply(&mut globals, "info [图片=a.png/]").starts_with("info ok: 甲\n"));
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&client).unwrap();
+    }
+
+
+    #[test]
+    fn unset() {
+        let mut globals = globals();
+        reply(&mut globals, "set [图片=a/] 猫 狗");
+        assert_eq!(reply(&mut globals, "unset [图片=a/] 狗 鱼"), "unset ok: removed 狗");
+        assert_eq!(reply(&mut globals, "unset [图片=a/] 鱼"), "unset fail: no such word: 鱼");
+        assert_eq!(send(&mut globals, "狗"), vec!("query fail: not found"));
+        assert_eq!(send(&mut globals, "猫"), vec!("[图片=a/]"));
+
+        // 别的范围里的词不受影响
+        send_group(&mut globals, USER, GROUP, "set [图片=a/] 鱼");
+        assert_eq!(reply(&mut globals, "unset [图片=a/] 鱼"), "unset fail: no such word: 鱼");
+        assert_eq!(send_group(&mut globals, USER, GROUP, "鱼"), vec!("[图片=a/]"));
+
+        assert!(reply(&mut globals, "undo").starts_with("undo ok: #2 unset"));
+        assert_eq!(send(&mut globals, "狗"), vec!("[图片=a/]"));
+    }
+
+
+    #[test]
+    fn trash() {
+        let mut globals = globals();
+        reply(&mut globals, "set [图片=a/] 猫");
+        reply(&mut globals, "set [图片=b/] 狗");
+        assert_eq!(reply(&mut globals, "trash"), "trash ok: trash is empty");
+        reply(&mut globals, "delete [图片=a/]");
+        assert_eq!(send(&mut globals, "猫"), vec!("query fail: not found"));
+        assert!(reply(&mut globals, "trash").starts_with("trash ok: 1 pics\na at "));
+
+        assert_eq!(reply(&mut globals, "restore a"), "restore ok: a");
+        assert_eq!(reply(&mut globals, "restore a"), "restore fail: a is not in trash");
+        assert_eq!(send(&mut globals, "猫"), vec!("[图片=a/]"));
+
+        reply(&mut globals, "delete [图片=a/]");
+        assert_eq!(reply(&mut globals, "purge b"), "purge fail: b is not in trash");
+        assert_eq!(reply(&mut globals, "purge [图片=a/]"), "purge ok: a");
+        assert_eq!(reply(&mut globals, "restore a"), "restore fail: a is not in trash");
+        reply(&mut globals, "delete [图片=b/]");
+        assert_eq!(reply(&mut globals, "purge all"), "purge ok: 1 pics");
+        assert_eq!(reply(&mut globals, "count"), "count ok: 0");
+    }
+
+
+    #[test]
+    fn backup() {
+        let dir = temp_dir("backup");
+        let mut globals = globals();
+        assert_eq!(reply(&mut globals, "backup"), "backup fail: no backup_dir");
+
+        // 以前只到秒的文件名也认
+        fs::write(dir.join("tutu-20200101-000000.jsonl"), b"").unwrap();
+        globals.backup = Some(Arc::new(Mutex::new(Backup::new(dir.to_str().unwrap(), 0, 0, 0))));
+        reply(&mut globals, "set [图片=a/] 猫");
+        assert!(reply(&mut globals, "backup").starts_with("backup ok: tutu-"));
+        thread::sleep(Duration::from_millis(5));
+        assert!(reply(&mut globals, "backup").ends_with(".jsonl 2 records"));
+        assert!(reply(&mut globals, "backup list").starts_with("backup ok: 3 backups\ntutu-"));
+        assert!(!fs::read_dir(&dir).unwrap().any(|t| t.unwrap().file_name().to_string_lossy().ends_with(".tmp")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn search_globals() -> BotGlobals {
+        return BotGlobals::new(String::from(ADMIN), Box::new(MemoryStore::new()), None, 30, None, Some(SearchIndex::new()), None);
+    }
+
+    #[test]
+    fn search_index_requires_all_groups() {
+        let mut globals = search_globals();
+        reply(&mut globals, "set [图片=a/] 猫咪 生气");
+        reply(&mut globals, "set [图片=b/] 狗 开心");
+        assert_eq!(send(&mut globals, "猫咪 生气"), vec!("[图片=a/]"));
+        assert!(send(&mut globals, "咪 开心")[0].starts_with("query fail: not found"));
+        assert!(send(&mut globals, "猫咪 开心")[0].starts_with("query fail: not found"));
+    }
+
+    #[test]
+    fn search_index_follows_changes() {
+        let mut globals = search_globals();
+        reply(&mut globals, "set [图片=a/] 猫咪");
+        assert_eq!(send(&mut globals, "猫咪"), vec!("[图片=a/]"));
+        reply(&mut globals, "set [图片=b/] 小猫咪");
+        assert_eq!(send(&mut globals, "小猫").len(), 1);
+        reply(&mut globals, "unset [图片=a/] 猫咪");
+        assert_eq!(send(&mut globals, "猫咪"), vec!("[图片=b/]"));
+        reply(&mut globals, "delete [图片=b/]");
+        assert_eq!(send(&mut globals, "猫咪"), vec!("query fail: not found"));
+        reply(&mut globals, "restore [图片=b/]");
+        assert_eq!(send(&mut globals, "猫咪"), vec!("[图片=b/]"));
+    }
+
+    #[test]
+    fn dict() {
+        let mut globals = globals();
+        assert_eq!(reply(&mut globals, "dict"), "dict ok: no words");
+        assert_eq!(reply(&mut globals, "dict add 好开心 猫猫"), "dict ok: added 好开心 猫猫");
+        assert_eq!(reply(&mut globals, "dict list"), "dict ok: 2 words\n好开心 猫猫");
+        assert_eq!(reply(&mut globals, "dict remove 猫猫 狗狗"), "dict ok: removed 猫猫");
+        assert_eq!(reply(&mut globals, "dict remove 狗狗"), "dict fail: no such word");
+        assert!(reply(&mut globals, "dict foo").starts_with("dict fail: use "));
+    }
+
+    #[test]
+    fn segmented() {
+        let mut globals = globals();
+        reply(&mut globals, "set [图片=b/] 北京天安门");
+        globals.segmenter = Some(Segmenter::new(&mut *globals.store).unwrap());
+        assert_eq!(reply(&mut globals, "set [图片=a/] 我爱北京天安门"), "set ok");
+        assert!(reply(&mut globals, "info [图片=a/]").starts_with("info ok: 我 爱 北京 天安门\n"));
+        assert_eq!(send(&mut globals, "天安门"), vec!("[图片=a/]"));
+        // 以前没切开的词也能查到
+        assert_eq!(send(&mut globals, "北京天安门").len(), 2);
+
+        // set时用的话原样unset就能去掉
+        assert!(reply(&mut globals, "unset [图片=a/] 我爱北京天安门").starts_with("unset ok: removed "));
+        assert_eq!(reply(&mut globals, "info [图片=a/]"), "info ok: ");
+    }
+
+}
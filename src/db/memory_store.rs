@@ -0,0 +1,617 @@
+use chrono::{DateTime, Duration, Local, TimeZone};
+use std::cmp::Reverse;
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::{DbError, Dump, Orphans, PicDump, PicStore, PicWordDump, Rank, TagRecord, Tagger, WordQuery};
+use super::normalize::{self, WordForms};
+use super::query::maybe_similar;
+
+// 纯内存的存储，不落盘，用于demo和测试
+// 表结构与数据库保持一致：t_pic / t_word / t_pic_word / t_pic_alias
+#[derive(Debug)]
+pub struct MemoryStore {
+    pics: Vec<Pic>,
+    words: Vec<String>,
+    pic_words: Vec<PicWord>,
+    aliases: Vec<PicAlias>,
+    synonyms: Vec<Synonym>,
+    // 设置过的查询时是否也查全局图库
+    scopes: Vec<(String, bool)>,
+    dict_words: Vec<String>,
+
+    // 代替数据库的last_ts，毫秒时间戳，每次写入时至少递增1
+    clock: u64,
+    seed: u64,
+}
+
+#[derive(Debug)]
+struct Pic {
+    name: String,
+    hash: Option<String>,
+    phash: Option<u64>,
+    // 放进回收站的时间
+    deleted: Option<DateTime<Local>>,
+}
+
+#[derive(Debug)]
+struct PicAlias {
+    name: String,
+    pic: String,
+}
+
+#[derive(Debug)]
+struct Synonym {
+    group: u64,
+    word: String,
+}
+
+#[derive(Debug)]
+struct PicWord {
+    pic: String,
+    word: String,
+    scope: String,
+    tagger: Tagger,
+    created_ts: DateTime<Local>,
+    last_ts: u64,
+    use_count: u64,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|t| t.subsec_nanos() as u64)
+            .unwrap_or(0);
+        return MemoryStore { pics: vec!(), words: vec!(), pic_words: vec!(), aliases: vec!(), synonyms: vec!(), scopes: vec!(), dict_words: vec!(), clock: 0, seed: seed | 1 };
+    }
+
+    // 彻底删除图片，不再被用到的词也一起删掉
+    fn remove_pic(&mut self, pic: &str) {
+        let words: Vec<String> = self.pic_words.iter()
+            .filter(|t| t.pic == pic)
+            .map(|t| t.word.clone())
+            .collect();
+        self.pics.retain(|t| t.name != pic);
+        self.pic_words.retain(|t| t.pic != pic);
+        self.aliases.retain(|t| t.pic != pic);
+
+        let pic_words = &self.pic_words;
+        self.words.retain(|w| !words.contains(w) || pic_words.iter().any(|t| &t.word == w));
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock = (self.clock + 1).max(Local::now().timestamp_millis() as u64);
+        return self.clock;
+    }
+
+    // xorshift，够demo用了
+    fn rand(&mut self, n: usize) -> usize {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        return (self.seed % n as u64) as usize;
+    }
+}
+
+impl PicStore for MemoryStore {
+    fn append_word(&mut self, scope: &str, pic: &str, word: &str, tagger: &Tagger) -> Result<(), DbError> {
+        match self.pics.iter_mut().find(|t| t.name == pic) {
+            // 又有人set了，说明这张图片还有用
+            Some(t) => t.deleted = None,
+            None => self.pics.push(Pic { name: String::from(pic), hash: None, phash: None, deleted: None })
+        }
+
+        let words = word.split_whitespace();
+        for word in words {
+            if !self.words.iter().any(|t| t == word) {
+                self.words.push(String::from(word));
+            }
+
+            let last_ts = self.tick();
+            match self.pic_words.iter_mut().find(|t| t.pic == pic && t.word == word && t.scope == scope) {
+                // 重复set也算是最近用过
+                Some(t) => t.last_ts = last_ts,
+                None => self.pic_words.push(PicWord {
+                    pic: String::from(pic),
+                    word: String::from(word),
+                    scope: String::from(scope),
+                    tagger: tagger.clone(),
+                    created_ts: Local::now(),
+                    last_ts,
+                    use_count: 0,
+                })
+            }
+        }
+
+        return Ok(());
+    }
+
+    fn replace_word(&mut self, scope: &str, pic: &str, word: &str, tagger: &Tagger) -> Result<(), DbError> {
+        // 只替换词，图片本身的hash和别名都保留
+        self.pic_words.retain(|t| t.pic != pic || t.scope != scope);
+        self.append_word(scope, pic, word, tagger)?;
+        Ok(())
+    }
+
+    fn remove_word(&mut self, scope: &str, pic: &str, word: &str) -> Result<(), DbError> {
+        let words: Vec<&str> = word.split_whitespace().collect();
+        self.pic_words.retain(|t| t.pic != pic || t.scope != scope || !words.contains(&t.word.as_str()));
+
+        let pic_words = &self.pic_words;
+        self.words.retain(|w| !words.contains(&w.as_str()) || pic_words.iter().any(|t| &t.word == w));
+        return Ok(());
+    }
+
+    fn delete_pic(&mut self, pic: &str) -> Result<(), DbError> {
+        for t in self.pics.iter_mut().filter(|t| t.name == pic && t.deleted.is_none()) {
+            t.deleted = Some(Local::now());
+        }
+        return Ok(());
+    }
+
+    fn restore_pic(&mut self, pic: &str) -> Result<bool, DbError> {
+        let mut restored = false;
+        for t in self.pics.iter_mut().filter(|t| t.name == pic && t.deleted.is_some()) {
+            t.deleted = None;
+            restored = true;
+        }
+        return Ok(restored);
+    }
+
+    fn list_trash(&mut self) -> Result<Vec<(String, String)>, DbError> {
+        let mut list: Vec<(&Pic, DateTime<Local>)> = self.pics.iter()
+            .filter_map(|t| t.deleted.map(|d| (t, d)))
+            .collect();
+        list.sort_by_key(|t| Reverse(t.1));
+        return Ok(list.iter().map(|t| (t.0.name.clone(), t.1.format("%Y-%m-%d %H:%M:%S").to_string())).collect());
+    }
+
+    fn purge_pic(&mut self, pic: &str) -> Result<bool, DbError> {
+        if !self.pics.iter().any(|t| t.name == pic && t.deleted.is_some()) {
+            return Ok(false);
+        }
+        self.remove_pic(pic);
+        return Ok(true);
+    }
+
+    fn purge_trash(&mut self, days: u32) -> Result<Vec<String>, DbError> {
+        let before = Local::now() - Duration::days(days as i64);
+        let pics: Vec<String> = self.pics.iter()
+            .filter(|t| t.deleted.map(|d| d <= before).unwrap_or(false))
+            .map(|t| t.name.clone())
+            .collect();
+        for pic in pics.iter() {
+            self.remove_pic(pic);
+        }
+        return Ok(pics);
+    }
+
+    fn query_pic(&mut self, scopes: &[String], query: &WordQuery, rank: Rank) -> Result<Vec<String>, DbError> {
+        if query.is_empty() {
+            return Ok(vec!());
+        }
+
+        // 满足条件的图片，以及命中的关联里最新的last_ts和use_count的总和
+        let words = query.words();
+        let mut found: Vec<(&str, u64, u64)> = vec!();
+        for p in self.pics.iter().filter(|t| t.deleted.is_none()) {
+            let pic_words: Vec<&PicWord> = self.pic_words.iter()
+                .filter(|t| t.pic == p.name && scopes.contains(&t.scope))
+                .collect();
+            let all: Vec<&str> = pic_words.iter().map(|t| t.word.as_str()).collect();
+            if !query.matches(&all) {
+                continue;
+            }
+            let hits = pic_words.iter().filter(|t| words.contains(&t.word.as_str()));
+            let last_ts = hits.clone().map(|t| t.last_ts).max().unwrap_or(0);
+            let use_count = hits.map(|t| t.use_count).sum();
+            found.push((&p.name, last_ts, use_count));
+        }
+
+        // 选出排在最前的一张图片，排序方式与数据库的Rank::order_by一致
+        let pic = match rank {
+            Rank::Newest => found.iter().max_by_key(|t| t.1),
+            Rank::MostUsed => found.iter().max_by_key(|t| (t.2, t.1)),
+            Rank::LeastRecent => found.iter().min_by_key(|t| t.1),
+        }.map(|t| String::from(t.0));
+        if pic.is_none() {
+            return Ok(vec!());
+        }
+        let pic = pic.unwrap();
+
+        // 以及随机的一张图片
+        let others: Vec<String> = found.iter()
+            .filter(|t| t.0 != pic)
+            .map(|t| String::from(t.0))
+            .collect();
+        if others.is_empty() {
+            return Ok(vec!(pic));
+        }
+        let pic2 = others[self.rand(others.len())].clone();
+        return Ok(vec!(pic, pic2));
+    }
+
+    fn touch_pic(&mut self, scopes: &[String], pic: &str, word: &str) -> Result<(), DbError> {
+        let last_ts = self.tick();
+        for t in self.pic_words.iter_mut().filter(|t| t.pic == pic && t.word == word && scopes.contains(&t.scope)) {
+            t.last_ts = last_ts;
+            t.use_count += 1;
+        }
+        return Ok(());
+    }
+
+    fn random_pic(&mut self, scopes: &[String]) -> Result<String, DbError> {
+        let pic_words = &self.pic_words;
+        let pics: Vec<String> = self.pics.iter()
+            .filter(|p| p.deleted.is_none())
+            .filter(|p| pic_words.iter().any(|t| t.pic == p.name && scopes.contains(&t.scope)))
+            .map(|p| p.name.clone())
+            .collect();
+        if pics.is_empty() {
+            return Ok(String::new());
+        }
+        let i = self.rand(pics.len());
+        return Ok(pics[i].clone());
+    }
+
+    fn list_pic_words(&mut self, pic: &str) -> Result<String, DbError> {
+        let words: Vec<&str> = self.pic_words.iter()
+            .filter(|t| t.pic == pic)
+            .map(|t| t.word.as_str())
+            .collect();
+
+        let words = words.join(" ");
+        return Ok(words);
+    }
+
+    fn list_pic_tags(&mut self, pic: &str) -> Result<Vec<TagRecord>, DbError> {
+        // pic_words本身就是按加的先后排列的
+        let list = self.pic_words.iter()
+            .filter(|t| t.pic == pic)
+            .map(|t| TagRecord {
+                word: t.word.clone(),
+                scope: t.scope.clone(),
+                tagger: t.tagger.clone(),
+                created_ts: Some(t.created_ts.format("%Y-%m-%d %H:%M:%S").to_string()),
+            })
+            .collect();
+        return Ok(list);
+    }
+
+    fn count_pic(&mut self) -> Result<u64, DbError> {
+        return Ok(self.pics.iter().filter(|t| t.deleted.is_none()).count() as u64);
+    }
+
+    fn list_pics(&mut self) -> Result<Vec<String>, DbError> {
+        return Ok(self.pics.iter().map(|t| t.name.clone()).collect());
+    }
+
+    fn find_words_by_forms(&mut self, word: &str) -> Result<Vec<String>, DbError> {
+        // 内存里不另外存，每次现算
+        let (simplified, pinyin) = normalize::query_forms(word);
+        let words = self.words.iter()
+            .filter(|t| {
+                let forms = WordForms::of(t);
+                forms.simplified == simplified || forms.pinyin == pinyin || forms.initials == pinyin
+            })
+            .cloned()
+            .collect();
+        return Ok(words);
+    }
+
+    fn update_word_forms(&mut self) -> Result<u64, DbError> {
+        return Ok(0);
+    }
+
+    fn add_synonyms(&mut self, words: &[&str]) -> Result<(), DbError> {
+        // 这些词已经所在的组都并到第一组里，都还没有组的话新建一组
+        let mut groups: Vec<u64> = vec!();
+        for t in self.synonyms.iter().filter(|t| words.contains(&t.word.as_str())) {
+            if !groups.contains(&t.group) {
+                groups.push(t.group);
+            }
+        }
+        let group = match groups.first() {
+            Some(t) => *t,
+            None => self.synonyms.iter().map(|t| t.group).max().unwrap_or(0) + 1
+        };
+        for t in self.synonyms.iter_mut().filter(|t| groups.contains(&t.group)) {
+            t.group = group;
+        }
+
+        for word in words {
+            if !self.synonyms.iter().any(|t| t.word == *word) {
+                self.synonyms.push(Synonym { group, word: String::from(*word) });
+            }
+        }
+        return Ok(());
+    }
+
+    fn remove_synonym(&mut self, word: &str) -> Result<bool, DbError> {
+        let group = match self.synonyms.iter().find(|t| t.word == word) {
+            Some(t) => t.group,
+            None => return Ok(false)
+        };
+        self.synonyms.retain(|t| t.word != word);
+
+        // 只剩一个词的组没有意义了
+        if self.synonyms.iter().filter(|t| t.group == group).count() < 2 {
+            self.synonyms.retain(|t| t.group != group);
+        }
+        return Ok(true);
+    }
+
+    fn list_synonyms(&mut self) -> Result<Vec<Vec<String>>, DbError> {
+        let mut groups: Vec<(u64, Vec<String>)> = vec!();
+        for t in self.synonyms.iter() {
+            match groups.iter_mut().find(|g| g.0 == t.group) {
+                Some(g) => g.1.push(t.word.clone()),
+                None => groups.push((t.group, vec!(t.word.clone())))
+            }
+        }
+        return Ok(groups.into_iter().map(|t| t.1).collect());
+    }
+
+    fn find_synonyms(&mut self, word: &str) -> Result<Vec<String>, DbError> {
+        let group = match self.synonyms.iter().find(|t| t.word == word) {
+            Some(t) => t.group,
+            None => return Ok(vec!())
+        };
+        let words = self.synonyms.iter()
+            .filter(|t| t.group == group && t.word != word)
+            .map(|t| t.word.clone())
+            .collect();
+        return Ok(words);
+    }
+
+    fn list_similar_words(&mut self, scopes: &[String], word: &str) -> Result<Vec<String>, DbError> {
+        let pic_words = &self.pic_words;
+        let pics = &self.pics;
+        let words = self.words.iter()
+            .filter(|w| maybe_similar(word, w))
+            .filter(|w| pic_words.iter().any(|t| &t.word == *w && scopes.contains(&t.scope)
+                && pics.iter().any(|p| p.name == t.pic && p.deleted.is_none())))
+            .cloned()
+            .collect();
+        return Ok(words);
+    }
+
+    fn add_dict_words(&mut self, words: &[&str]) -> Result<(), DbError> {
+        for word in words {
+            if !self.dict_words.iter().any(|t| t == word) {
+                self.dict_words.push(String::from(*word));
+            }
+        }
+        return Ok(());
+    }
+
+    fn remove_dict_word(&mut self, word: &str) -> Result<bool, DbError> {
+        let count = self.dict_words.len();
+        self.dict_words.retain(|t| t != word);
+        return Ok(self.dict_words.len() < count);
+    }
+
+    fn list_dict_words(&mut self) -> Result<Vec<String>, DbError> {
+        return Ok(self.dict_words.clone());
+    }
+
+    fn search_global(&mut self, scope: &str) -> Result<bool, DbError> {
+        let search_global = self.scopes.iter()
+            .find(|t| t.0 == scope)
+            .map(|t| t.1);
+        return Ok(search_global.unwrap_or(true));
+    }
+
+    fn set_search_global(&mut self, scope: &str, search_global: bool) -> Result<(), DbError> {
+        self.scopes.retain(|t| t.0 != scope);
+        self.scopes.push((String::from(scope), search_global));
+        return Ok(());
+    }
+
+    fn find_orphans(&mut self) -> Result<Orphans, DbError> {
+        let pic_words = &self.pic_words;
+        let pics = self.pics.iter()
+            .filter(|p| !pic_words.iter().any(|t| t.pic == p.name))
+            .map(|p| p.name.clone())
+            .collect();
+        let words = self.words.iter()
+            .filter(|w| !pic_words.iter().any(|t| &t.word == *w))
+            .cloned()
+            .collect();
+        return Ok(Orphans { pics, words, pic_words: 0 });
+    }
+
+    fn remove_orphans(&mut self, orphans: &Orphans) -> Result<(), DbError> {
+        let pic_words = &self.pic_words;
+        self.pics.retain(|p| !orphans.pics.contains(&p.name) || pic_words.iter().any(|t| t.pic == p.name));
+        self.words.retain(|w| !orphans.words.contains(w) || pic_words.iter().any(|t| &t.word == w));
+        return Ok(());
+    }
+
+    fn resolve_pic(&mut self, pic: &str) -> Result<String, DbError> {
+        let name = self.aliases.iter()
+            .find(|t| t.name == pic)
+            .map(|t| t.pic.clone());
+        return Ok(name.unwrap_or_else(|| String::from(pic)));
+    }
+
+    fn find_pic_by_hash(&mut self, pic: &str, hash: &str) -> Result<Option<String>, DbError> {
+        let name = self.pics.iter()
+            .find(|t| t.name != pic && t.deleted.is_none() && t.hash.as_deref() == Some(hash))
+            .map(|t| t.name.clone());
+        return Ok(name);
+    }
+
+    fn set_pic_hash(&mut self, pic: &str, hash: &str) -> Result<(), DbError> {
+        for t in self.pics.iter_mut().filter(|t| t.name == pic) {
+            t.hash = Some(String::from(hash));
+        }
+        return Ok(());
+    }
+
+    fn list_unhashed_pics(&mut self) -> Result<Vec<String>, DbError> {
+        let pics = self.pics.iter()
+            .filter(|t| t.hash.is_none())
+            .map(|t| t.name.clone())
+            .collect();
+        return Ok(pics);
+    }
+
+    fn append_word_dedup(&mut self, scope: &str, pic: &str, word: &str, tagger: &Tagger, hash: Option<&str>) -> Result<Option<String>, DbError> {
+        self.append_word(scope, pic, word, tagger)?;
+        return match hash {
+            Some(t) => self.dedup_pic(pic, t),
+            None => Ok(None)
+        };
+    }
+
+    fn dedup_pic(&mut self, pic: &str, hash: &str) -> Result<Option<String>, DbError> {
+        if let Some(t) = self.find_pic_by_hash(pic, hash)? {
+            self.merge_pic(pic, &t)?;
+            return Ok(Some(t));
+        }
+        self.set_pic_hash(pic, hash)?;
+        return Ok(None);
+    }
+
+    fn set_pic_phash(&mut self, pic: &str, phash: u64) -> Result<(), DbError> {
+        for t in self.pics.iter_mut().filter(|t| t.name == pic) {
+            t.phash = Some(phash);
+        }
+        return Ok(());
+    }
+
+    fn list_pic_phashes(&mut self) -> Result<Vec<(String, Option<u64>)>, DbError> {
+        let list = self.pics.iter()
+            .filter(|t| t.deleted.is_none())
+            .map(|t| (t.name.clone(), t.phash))
+            .collect();
+        return Ok(list);
+    }
+
+    fn merge_pic(&mut self, from: &str, into: &str) -> Result<(), DbError> {
+        if from == into || !self.pics.iter().any(|t| t.name == from) {
+            return Ok(());
+        }
+
+        // 词的合并与append_word走同一条路径，每个词在原来的范围里合并，仍然记在原来加它的人名下
+        let words: Vec<(String, String, Tagger)> = self.pic_words.iter()
+            .filter(|t| t.pic == from)
+            .map(|t| (t.scope.clone(), t.word.clone(), t.tagger.clone()))
+            .collect();
+        for (scope, word, tagger) in words.iter() {
+            self.append_word(scope, into, word, tagger)?;
+        }
+
+        self.pic_words.retain(|t| t.pic != from);
+        for t in self.aliases.iter_mut().filter(|t| t.pic == from) {
+            t.pic = String::from(into);
+        }
+        self.pics.retain(|t| t.name != from);
+        self.aliases.push(PicAlias { name: String::from(from), pic: String::from(into) });
+        return Ok(());
+    }
+
+    fn list_pic_links(&mut self, pic: Option<&str>) -> Result<Vec<PicWordDump>, DbError> {
+        let pics = &self.pics;
+        let links = self.pic_words.iter()
+            .filter(|t| (pic.is_none() || pic == Some(t.pic.as_str())) && pics.iter().any(|p| p.name == t.pic && p.deleted.is_none()))
+            .map(pic_word_dump)
+            .collect();
+        return Ok(links);
+    }
+
+    fn export(&mut self) -> Result<Dump, DbError> {
+        let pics = self.pics.iter()
+            .map(|t| PicDump {
+                name: t.name.clone(),
+                hash: t.hash.clone(),
+                phash: t.phash,
+                deleted_ts: t.deleted.map(|d| d.timestamp_millis()),
+            })
+            .collect();
+        let pic_words = self.pic_words.iter().map(pic_word_dump).collect();
+        let aliases = self.aliases.iter().map(|t| (t.name.clone(), t.pic.clone())).collect();
+        return Ok(Dump { pics, pic_words, aliases, synonyms: self.list_synonyms()?, scopes: self.scopes.clone(), dict_words: self.dict_words.clone() });
+    }
+
+    fn import(&mut self, dump: &Dump) -> Result<(), DbError> {
+        for pic in dump.pics.iter() {
+            match self.pics.iter_mut().find(|t| t.name == pic.name) {
+                Some(t) => {
+                    t.hash = t.hash.take().or_else(|| pic.hash.clone());
+                    t.phash = t.phash.or(pic.phash);
+                }
+                None => self.pics.push(Pic {
+                    name: pic.name.clone(),
+                    hash: pic.hash.clone(),
+                    phash: pic.phash,
+                    deleted: pic.deleted_ts.and_then(|t| Local.timestamp_millis_opt(t).single()),
+                })
+            }
+        }
+
+        for t in dump.pic_words.iter() {
+            if !self.pics.iter().any(|p| p.name == t.pic) {
+                self.pics.push(Pic { name: t.pic.clone(), hash: None, phash: None, deleted: None });
+            }
+            if !self.words.contains(&t.word) {
+                self.words.push(t.word.clone());
+            }
+            let last_ts = t.last_ts.max(0) as u64;
+            match self.pic_words.iter_mut().find(|p| p.pic == t.pic && p.word == t.word && p.scope == t.scope) {
+                Some(p) => {
+                    p.last_ts = p.last_ts.max(last_ts);
+                    p.use_count = p.use_count.max(t.use_count);
+                }
+                None => self.pic_words.push(PicWord {
+                    pic: t.pic.clone(),
+                    word: t.word.clone(),
+                    scope: t.scope.clone(),
+                    tagger: t.tagger.clone(),
+                    created_ts: t.created_ts.and_then(|t| Local.timestamp_millis_opt(t).single()).unwrap_or_else(Local::now),
+                    last_ts,
+                    use_count: t.use_count,
+                })
+            }
+        }
+        self.clock = self.clock.max(self.pic_words.iter().map(|t| t.last_ts).max().unwrap_or(0));
+
+        // 别名只在还没有同名图片时才加上
+        for (name, pic) in dump.aliases.iter() {
+            if !self.pics.iter().any(|t| &t.name == name) && !self.aliases.iter().any(|t| &t.name == name) {
+                self.aliases.push(PicAlias { name: name.clone(), pic: pic.clone() });
+            }
+        }
+
+        for words in dump.synonyms.iter() {
+            let words: Vec<&str> = words.iter().map(|t| t.as_str()).collect();
+            self.add_synonyms(&words)?;
+        }
+
+        // 已经设置过的范围保留原来的设置
+        for (scope, search_global) in dump.scopes.iter() {
+            if !self.scopes.iter().any(|t| &t.0 == scope) {
+                self.scopes.push((scope.clone(), *search_global));
+            }
+        }
+
+        let words: Vec<&str> = dump.dict_words.iter().map(|t| t.as_str()).collect();
+        self.add_dict_words(&words)?;
+        return Ok(());
+    }
+
+    fn migrate(&mut self) -> Result<Vec<String>, DbError> {
+        return Ok(vec!());
+    }
+}
+
+fn pic_word_dump(t: &PicWord) -> PicWordDump {
+    return PicWordDump {
+        pic: t.pic.clone(),
+        word: t.word.clone(),
+        scope: t.scope.clone(),
+        tagger: t.tagger.clone(),
+        created_ts: Some(t.created_ts.timestamp_millis()),
+        last_ts: t.last_ts as i64,
+        use_count: t.use_count,
+    };
+}
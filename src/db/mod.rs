@@ -0,0 +1,297 @@
+use mysql;
+use rusqlite;
+use std::fmt;
+use std::io;
+
+mod memory_store;
+mod mysql_store;
+mod normalize;
+mod query;
+mod schema;
+mod sqlite_store;
+mod zh_table;
+
+pub use self::memory_store::MemoryStore;
+pub use self::mysql_store::{DbInfo, MysqlStore};
+pub use self::query::{similar_words, WordQuery};
+pub use self::sqlite_store::SqliteStore;
+
+// 全局图库，各个群和私聊都可以设置是否也查这里
+pub const GLOBAL_SCOPE: &str = "global";
+
+// 群里set的图片只在这个群里能查到
+pub fn group_scope(group_id: &str) -> String {
+    return format!("g:{}", group_id);
+}
+
+// 私聊set的图片只有这个人能查到
+pub fn user_scope(user_id: &str) -> String {
+    return format!("u:{}", user_id);
+}
+
+// set词的人，私聊时group_id为空
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tagger {
+    pub user_id: String,
+    pub group_id: String,
+}
+
+// 图片上的一个词是谁在什么时候加的，升级前就有的词不知道是谁加的，tagger为空，created_ts为None
+#[derive(Debug)]
+pub struct TagRecord {
+    pub word: String,
+    pub scope: String,
+    pub tagger: Tagger,
+    pub created_ts: Option<String>,
+}
+
+// 导出导入用的全部数据，与存储方式无关，时间都是毫秒时间戳
+#[derive(Debug, Default, PartialEq)]
+pub struct Dump {
+    pub pics: Vec<PicDump>,
+    pub pic_words: Vec<PicWordDump>,
+    // 被合并掉的图片名，以及保留下来的图片名
+    pub aliases: Vec<(String, String)>,
+    pub synonyms: Vec<Vec<String>>,
+    // 设置过的查询时是否也查全局图库
+    pub scopes: Vec<(String, bool)>,
+    pub dict_words: Vec<String>,
+}
+
+impl Dump {
+    pub fn is_empty(&self) -> bool {
+        return self.pics.is_empty() && self.pic_words.is_empty() && self.aliases.is_empty()
+            && self.synonyms.is_empty() && self.scopes.is_empty() && self.dict_words.is_empty();
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PicDump {
+    pub name: String,
+    pub hash: Option<String>,
+    pub phash: Option<u64>,
+    // 在回收站里的是放进去的时间
+    pub deleted_ts: Option<i64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PicWordDump {
+    pub pic: String,
+    pub word: String,
+    pub scope: String,
+    pub tagger: Tagger,
+    pub created_ts: Option<i64>,
+    pub last_ts: i64,
+    pub use_count: u64,
+}
+
+// 图片与词的存储，bot只依赖这个trait，不关心背后是哪种数据库
+pub trait PicStore {
+    // 在scope里给图片追加词，多个词以空白分隔，已经有的词保留原来是谁加的
+    // 图片在回收站里的话会被拿回来
+    fn append_word(&mut self, scope: &str, pic: &str, word: &str, tagger: &Tagger) -> Result<(), DbError>;
+
+    // 用新的词替换掉图片在scope里原有的所有词
+    fn replace_word(&mut self, scope: &str, pic: &str, word: &str, tagger: &Tagger) -> Result<(), DbError>;
+
+    // 把这些词从图片在scope里的词中去掉，多个词以空白分隔，图片上没有的词忽略
+    // 去掉之后不再被任何图片用到的词也一起删掉
+    fn remove_word(&mut self, scope: &str, pic: &str, word: &str) -> Result<(), DbError>;
+
+    // 把图片放进回收站，词和别名都还留着，查询和random不会再发出这张图片
+    fn delete_pic(&mut self, pic: &str) -> Result<(), DbError>;
+
+    // 把图片从回收站里拿回来，返回图片是否在回收站里
+    fn restore_pic(&mut self, pic: &str) -> Result<bool, DbError>;
+
+    // 回收站里的图片及放进去的时间，最近放进去的在前
+    fn list_trash(&mut self) -> Result<Vec<(String, String)>, DbError>;
+
+    // 彻底删除回收站里的一张图片，不再被用到的词也一起删掉，返回图片是否在回收站里
+    fn purge_pic(&mut self, pic: &str) -> Result<bool, DbError>;
+
+    // 彻底删除在回收站里放了至少days天的图片，返回删掉了哪些
+    fn purge_trash(&mut self, days: u32) -> Result<Vec<String>, DbError>;
+
+    // 在scopes里满足查询条件的图片里按rank排在最前的一张，以及随机的另一张
+    fn query_pic(&mut self, scopes: &[String], query: &WordQuery, rank: Rank) -> Result<Vec<String>, DbError>;
+
+    // 图片因为这个词被发了出去，刷新scopes里的last_ts并记一次使用
+    fn touch_pic(&mut self, scopes: &[String], pic: &str, word: &str) -> Result<(), DbError>;
+
+    // scopes里随机的一张图片，没有图片时返回空字符串
+    fn random_pic(&mut self, scopes: &[String]) -> Result<String, DbError>;
+
+    // 图片下挂的所有词，以空格分隔
+    fn list_pic_words(&mut self, pic: &str) -> Result<String, DbError>;
+
+    // 图片上每个词是谁在什么时候加的，按加的先后排列
+    fn list_pic_tags(&mut self, pic: &str) -> Result<Vec<TagRecord>, DbError>;
+
+    // 不算回收站里的
+    fn count_pic(&mut self) -> Result<u64, DbError>;
+
+    // 所有图片的名字，包括回收站里的
+    fn list_pics(&mut self) -> Result<Vec<String>, DbError>;
+
+    // 简体、拼音或者拼音首字母与word一致的词，word可以是繁体字或者拼音
+    fn find_words_by_forms(&mut self, word: &str) -> Result<Vec<String>, DbError>;
+
+    // 给还没有简体、拼音等写法的词补上，返回补了多少个
+    fn update_word_forms(&mut self) -> Result<u64, DbError>;
+
+    // 把这些词设为同义词，其中已经有同义词的，原来的那些同义词也一起并到一组
+    fn add_synonyms(&mut self, words: &[&str]) -> Result<(), DbError>;
+
+    // 把词从所在的同义词组里去掉，返回这个词原来是否在某一组里
+    fn remove_synonym(&mut self, word: &str) -> Result<bool, DbError>;
+
+    // 所有的同义词组
+    fn list_synonyms(&mut self) -> Result<Vec<Vec<String>>, DbError>;
+
+    // 与word同一组的其它词
+    fn find_synonyms(&mut self, word: &str) -> Result<Vec<String>, DbError>;
+
+    // 分词用的自定义词，已经有的忽略
+    fn add_dict_words(&mut self, words: &[&str]) -> Result<(), DbError>;
+
+    // 返回这个词原来是否在自定义词里
+    fn remove_dict_word(&mut self, word: &str) -> Result<bool, DbError>;
+
+    fn list_dict_words(&mut self) -> Result<Vec<String>, DbError>;
+
+    // scopes里用到的、可能与word相近的词，模糊查询时在这里面找，条件见query::maybe_similar
+    fn list_similar_words(&mut self, scopes: &[String], word: &str) -> Result<Vec<String>, DbError>;
+
+    // 查询时是否也查全局图库，没有设置过的默认是查的
+    fn search_global(&mut self, scope: &str) -> Result<bool, DbError>;
+
+    fn set_search_global(&mut self, scope: &str, search_global: bool) -> Result<(), DbError>;
+
+    fn find_orphans(&mut self) -> Result<Orphans, DbError>;
+
+    // 删除前会再检查一遍，这期间又被用上的图片和词不会被删掉
+    fn remove_orphans(&mut self, orphans: &Orphans) -> Result<(), DbError>;
+
+    // 图片名如果是被合并掉的别名，返回保留下来的图片名，否则原样返回
+    fn resolve_pic(&mut self, pic: &str) -> Result<String, DbError>;
+
+    // 内容hash为hash的另一张图片，回收站里的不算，没有时返回None
+    fn find_pic_by_hash(&mut self, pic: &str, hash: &str) -> Result<Option<String>, DbError>;
+
+    fn set_pic_hash(&mut self, pic: &str, hash: &str) -> Result<(), DbError>;
+
+    // 还没有计算过内容hash的图片
+    fn list_unhashed_pics(&mut self) -> Result<Vec<String>, DbError>;
+
+    // append_word与dedup_pic在同一个事务里完成，没有hash时只追加词
+    fn append_word_dedup(&mut self, scope: &str, pic: &str, word: &str, tagger: &Tagger, hash: Option<&str>) -> Result<Option<String>, DbError>;
+
+    // hash相同的另一张图片已经存在时把pic合并过去并返回它，否则记下pic的hash，查找与合并在同一个事务里
+    fn dedup_pic(&mut self, pic: &str, hash: &str) -> Result<Option<String>, DbError>;
+
+    // 感知hash，用来找出看起来差不多的图片
+    fn set_pic_phash(&mut self, pic: &str, phash: u64) -> Result<(), DbError>;
+
+    // 回收站以外的图片及其感知hash，还没算过的为None
+    fn list_pic_phashes(&mut self) -> Result<Vec<(String, Option<u64>)>, DbError>;
+
+    // 把from的词连同是谁加的都追加到into上，删掉from，from之后作为into的别名
+    fn merge_pic(&mut self, from: &str, into: &str) -> Result<(), DbError>;
+
+    // 回收站以外的图片上的词，以及last_ts和use_count，pic为None时是所有图片的，给搜索索引用
+    fn list_pic_links(&mut self, pic: Option<&str>) -> Result<Vec<PicWordDump>, DbError>;
+
+    // 导出全部数据，包括回收站里的图片
+    fn export(&mut self) -> Result<Dump, DbError>;
+
+    // 把导出的数据合并进来，已经有的图片和关联保留原来的内容，只补上没有的hash，last_ts和use_count取较大的
+    fn import(&mut self, dump: &Dump) -> Result<(), DbError>;
+
+    // 建表或者把表结构升级到最新版本，返回这次执行了的版本
+    fn migrate(&mut self) -> Result<Vec<String>, DbError>;
+}
+
+// 启动时先把表结构准备好
+pub fn init(store: &mut dyn PicStore) -> Result<(), DbError> {
+    for version in store.migrate()? {
+        println!("db migrated: {}", version);
+    }
+
+    let count = store.update_word_forms()?;
+    if count > 0 {
+        println!("word forms updated: {}", count);
+    }
+    return Ok(());
+}
+
+// 查询时挑图片的方式，last_ts在set和发图时都会刷新
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rank {
+    // 最近设置过或者发过的
+    Newest,
+    // 发过次数最多的
+    MostUsed,
+    // 最久没有设置过也没有发过的，反复查询会轮流发出每一张图片
+    LeastRecent,
+}
+
+impl Rank {
+    // 对应WordQuery::to_sql里按图片分组之后的排序，last_ts相同的按id区分先后
+    fn order_by(&self) -> &'static str {
+        return match self {
+            Rank::Newest => "MAX(j.last_ts) DESC, MAX(j.id) DESC",
+            Rank::MostUsed => "SUM(j.use_count) DESC, MAX(j.last_ts) DESC, MAX(j.id) DESC",
+            Rank::LeastRecent => "MAX(j.last_ts), MAX(j.id)",
+        };
+    }
+}
+
+// clean时找出来的没用的数据
+#[derive(Debug, Default)]
+pub struct Orphans {
+    // 没有任何词的图片
+    pub pics: Vec<String>,
+    // 没有被任何图片用到的词
+    pub words: Vec<String>,
+    // 图片或词已经不存在了的关联
+    pub pic_words: u64,
+}
+
+#[derive(Debug)]
+pub enum DbError {
+    Mysql(mysql::Error),
+    Sqlite(rusqlite::Error),
+    // 等待了这么多毫秒仍然拿不到空闲连接
+    PoolExhausted(u32),
+    Io(io::Error),
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            DbError::Mysql(t) => write!(f, "{}", t),
+            DbError::Sqlite(t) => write!(f, "{}", t),
+            DbError::PoolExhausted(t) => write!(f, "db busy, no free connection in {}ms", t),
+            DbError::Io(t) => write!(f, "{}", t),
+        };
+    }
+}
+
+impl From<mysql::Error> for DbError {
+    fn from(e: mysql::Error) -> DbError {
+        return DbError::Mysql(e);
+    }
+}
+
+impl From<rusqlite::Error> for DbError {
+    fn from(e: rusqlite::Error) -> DbError {
+        return DbError::Sqlite(e);
+    }
+}
+
+impl From<io::Error> for DbError {
+    fn from(e: io::Error) -> DbError {
+        return DbError::Io(e);
+    }
+}
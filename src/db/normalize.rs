@@ -0,0 +1,95 @@
+use super::zh_table::{PINYIN, T2S};
+
+// 词的几种写法，存在t_word里用来匹配繁体、拼音和拼音首字母
+#[derive(Debug)]
+pub struct WordForms {
+    pub simplified: String,
+    pub pinyin: String,
+    pub initials: String,
+}
+
+impl WordForms {
+    pub fn of(word: &str) -> WordForms {
+        let simplified: String = word.chars().map(to_simplified).collect();
+
+        // 不是汉字的字原样保留，字母统一成小写
+        let mut pinyin = String::new();
+        let mut initials = String::new();
+        for c in simplified.chars() {
+            match PINYIN.binary_search_by_key(&c, |t| t.0) {
+                Ok(i) => {
+                    pinyin.push_str(PINYIN[i].1);
+                    initials.push_str(&PINYIN[i].1[..1]);
+                }
+                Err(_) => {
+                    pinyin.extend(c.to_lowercase());
+                    initials.extend(c.to_lowercase());
+                }
+            }
+        }
+
+        return WordForms { simplified, pinyin, initials };
+    }
+}
+
+// 查询时用的写法：有汉字的按简体匹配，没有汉字的按拼音或者拼音首字母匹配
+// 不用的那一种返回空字符串，不会匹配上任何词
+pub fn query_forms(word: &str) -> (String, String) {
+    let forms = WordForms::of(word);
+    return if word.chars().any(is_han) {
+        (forms.simplified, String::new())
+    } else {
+        (forms.simplified, forms.pinyin)
+    };
+}
+
+fn to_simplified(c: char) -> char {
+    return match T2S.binary_search_by_key(&c, |t| t.0) {
+        Ok(i) => T2S[i].1,
+        Err(_) => c
+    };
+}
+
+fn is_han(c: char) -> bool {
+    return ('\u{4e00}'..='\u{9fff}').contains(&c);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forms() {
+        let forms = WordForms::of("猫咪");
+        assert_eq!((forms.simplified.as_str(), forms.pinyin.as_str(), forms.initials.as_str()), ("猫咪", "maomi", "mm"));
+
+        // 繁体字先转成简体再取拼音
+        let forms = WordForms::of("貓咪");
+        assert_eq!((forms.simplified.as_str(), forms.pinyin.as_str(), forms.initials.as_str()), ("猫咪", "maomi", "mm"));
+
+        // 不是汉字的字原样保留，字母转小写
+        let forms = WordForms::of("Hello龍2");
+        assert_eq!((forms.simplified.as_str(), forms.pinyin.as_str(), forms.initials.as_str()), ("Hello龙2", "hellolong2", "hellol2"));
+        let forms = WordForms::of("ABC!");
+        assert_eq!((forms.simplified.as_str(), forms.pinyin.as_str(), forms.initials.as_str()), ("ABC!", "abc!", "abc!"));
+    }
+
+    #[test]
+    fn query() {
+        // 有汉字时只按简体匹配
+        assert_eq!(query_forms("貓咪"), (String::from("猫咪"), String::new()));
+        assert_eq!(query_forms("MaoMi"), (String::from("MaoMi"), String::from("maomi")));
+        assert_eq!(query_forms("mm"), (String::from("mm"), String::from("mm")));
+    }
+
+    #[test]
+    fn han_range() {
+        assert!(is_han('\u{4e00}'));
+        assert!(is_han('猫'));
+        assert!(is_han('\u{9fff}'));
+        assert!(!is_han('\u{4dff}'));
+        assert!(!is_han('\u{a000}'));
+        assert!(!is_han('a'));
+        assert!(!is_han('，'));
+    }
+}
@@ -0,0 +1,289 @@
+use super::{DbError, PicStore};
+
+// 查询条件，空白分隔的词之间是AND，a|b表示a或b，-a表示不能有a
+#[derive(Debug, Default)]
+pub struct WordQuery {
+    // 每一组里至少要有一个词
+    pub all: Vec<Vec<String>>,
+    // 一个都不能有
+    pub none: Vec<String>,
+}
+
+impl WordQuery {
+    pub fn parse(text: &str) -> WordQuery {
+        // "猫 | 狗"与"猫|狗"等价
+        let text: Vec<&str> = text.split('|').map(|t| t.trim()).collect();
+        let text = text.join("|");
+
+        let mut query = WordQuery::default();
+        for word in text.split_whitespace() {
+            if word.starts_with('-') && word.len() > 1 {
+                query.none.push(String::from(&word[1..]));
+                continue;
+            }
+
+            let words: Vec<String> = word.split('|')
+                .filter(|t| !t.is_empty())
+                .map(String::from)
+                .collect();
+            if !words.is_empty() {
+                query.all.push(words);
+            }
+        }
+        return query;
+    }
+
+    // 只有排除的词时也算空查询
+    pub fn is_empty(&self) -> bool {
+        return self.all.is_empty();
+    }
+
+    // 查询里所有要找的词
+    pub fn words(&self) -> Vec<&str> {
+        let mut words: Vec<&str> = vec!();
+        for word in self.all.iter().flat_map(|t| t.iter()) {
+            if !words.contains(&word.as_str()) {
+                words.push(word);
+            }
+        }
+        return words;
+    }
+
+    // 每个词都加上繁体、拼音写法与它一致的词，以及这些词的同义词，排除的词也一样
+    pub fn with_forms(&self, store: &mut dyn PicStore) -> Result<WordQuery, DbError> {
+        let mut query = WordQuery::default();
+        for words in self.all.iter() {
+            query.all.push(expand(words, store)?);
+        }
+        query.none = expand(&self.none, store)?;
+        return Ok(query);
+
+        fn expand(words: &[String], store: &mut dyn PicStore) -> Result<Vec<String>, DbError> {
+            let mut list = words.to_vec();
+            for word in words.iter() {
+                add_all(&mut list, store.find_words_by_forms(word)?);
+            }
+            for word in list.clone().iter() {
+                add_all(&mut list, store.find_synonyms(word)?);
+            }
+            return Ok(list);
+        }
+
+        fn add_all(list: &mut Vec<String>, words: Vec<String>) {
+            for word in words {
+                if !list.contains(&word) {
+                    list.push(word);
+                }
+            }
+        }
+    }
+
+    // 模糊查询，每个要找的词都再加上与它相近的词作为"或"的条件，排除的词不变
+    pub fn fuzzy(&self, words: &[String]) -> WordQuery {
+        let all = self.all.iter()
+            .map(|t| {
+                let mut list = t.clone();
+                for word in t.iter() {
+                    for similar in similar_words(word, words) {
+                        if !list.iter().any(|t| t == similar) {
+                            list.push(String::from(similar));
+                        }
+                    }
+                }
+                list
+            })
+            .collect();
+        return WordQuery { all, none: self.none.clone() };
+    }
+
+    // 一张图片下挂的词是否满足查询条件
+    pub fn matches(&self, words: &[&str]) -> bool {
+        return self.all.iter().all(|t| t.iter().any(|w| words.contains(&w.as_str())))
+            && !self.none.iter().any(|w| words.contains(&w.as_str()));
+    }
+
+    // 拼出在scopes里查一张图片的sql，参数用?占位，按顺序返回
+    // 排序用的是命中的那些关联，last_ts取最新的一条，use_count取总和
+    pub fn to_sql(&self, scopes: &[String], exclude: Option<&str>, order_by: &str) -> (String, Vec<String>) {
+        let mut params = vec!();
+
+        let words = self.words();
+        let mut sql = format!(
+            "SELECT p.name
+             FROM t_pic p
+             JOIN t_pic_word j ON j.id_pic = p.id
+             JOIN t_word w ON j.id_word = w.id
+             WHERE p.deleted_ts IS NULL
+               AND j.scope IN ({})
+               AND w.word IN ({})", placeholders(scopes.len()), placeholders(words.len()));
+        params.extend(scopes.iter().cloned());
+        params.extend(words.iter().map(|t| String::from(*t)));
+
+        for words in self.all.iter() {
+            sql.push_str(&format!(
+                "
+               AND EXISTS (SELECT 1 FROM t_pic_word j2 JOIN t_word w2 ON j2.id_word = w2.id
+                           WHERE j2.id_pic = p.id AND j2.scope IN ({}) AND w2.word IN ({}))",
+                placeholders(scopes.len()), placeholders(words.len())));
+            params.extend(scopes.iter().cloned());
+            params.extend(words.iter().cloned());
+        }
+        if !self.none.is_empty() {
+            sql.push_str(&format!(
+                "
+               AND NOT EXISTS (SELECT 1 FROM t_pic_word j2 JOIN t_word w2 ON j2.id_word = w2.id
+                               WHERE j2.id_pic = p.id AND j2.scope IN ({}) AND w2.word IN ({}))",
+                placeholders(scopes.len()), placeholders(self.none.len())));
+            params.extend(scopes.iter().cloned());
+            params.extend(self.none.iter().cloned());
+        }
+        if let Some(t) = exclude {
+            sql.push_str("
+               AND p.name != ?");
+            params.push(String::from(t));
+        }
+
+        sql.push_str(&format!(
+            "
+             GROUP BY p.id, p.name
+             ORDER BY {}
+             LIMIT 1", order_by));
+        return (sql, params);
+    }
+}
+
+// IN (...)里的n个?
+pub fn placeholders(n: usize) -> String {
+    let list: Vec<&str> = (0..n).map(|_| "?").collect();
+    return list.join(", ");
+}
+
+// 与word相近的词，不包括word本身
+// 包含word的排在前面，然后是编辑距离不超过一半字数的，各自按差得多少排序
+pub fn similar_words<'a>(word: &str, words: &'a [String]) -> Vec<&'a str> {
+    const MAX: usize = 5;
+
+    let len = word.chars().count();
+    let mut found: Vec<(usize, usize, &str)> = vec!();
+    for t in words.iter().filter(|t| *t != word) {
+        if t.contains(word) {
+            found.push((0, t.chars().count() - len, t));
+            continue;
+        }
+        if !maybe_similar(word, t) {
+            continue;
+        }
+        let distance = edit_distance(word, t);
+        if distance <= len / 2 {
+            found.push((1, distance, t));
+        }
+    }
+
+    found.sort();
+    return found.into_iter().take(MAX).map(|t| t.2).collect();
+}
+
+// 先用便宜的条件筛掉不可能相近的词，编辑距离只在剩下的词里算
+// 包含word的，或者字数相差不超过一半、并且至少有一个相同的字的
+// 编辑距离不超过一半字数时至少有一个字没被改掉，所以一个相同的字都没有的词不用算
+pub fn maybe_similar(word: &str, t: &str) -> bool {
+    if t.contains(word) {
+        return true;
+    }
+    let len = word.chars().count();
+    let t_len = t.chars().count();
+    return len / 2 > 0
+        && t_len.max(len) - t_len.min(len) <= len / 2
+        && t.chars().any(|c| word.contains(c));
+}
+
+// 拼出在scopes里按maybe_similar的条件查词的sql，参数用?占位，按顺序返回
+// char_length是数据库里按字计算长度的函数
+pub fn similar_words_sql(scopes: &[String], word: &str, char_length: &str) -> (String, Vec<String>) {
+    let len = word.chars().count();
+    let mut sql = String::from(
+        "SELECT w.word
+         FROM t_word w
+         WHERE (w.word LIKE ? ESCAPE '!'");
+    let mut params = vec!(format!("%{}%", escape_like(word)));
+
+    if len / 2 > 0 {
+        let mut chars: Vec<char> = word.chars().collect();
+        chars.sort();
+        chars.dedup();
+        let like: Vec<&str> = chars.iter().map(|_| "w.word LIKE ? ESCAPE '!'").collect();
+        // 长度直接拼进sql里，sqlite里整数和字符串参数比较不会转换类型
+        sql.push_str(&format!(
+            "
+                OR ({}(w.word) BETWEEN {} AND {} AND ({}))", char_length, len - len / 2, len + len / 2, like.join(" OR ")));
+        params.extend(chars.iter().map(|t| format!("%{}%", escape_like(&t.to_string()))));
+    }
+
+    sql.push_str(&format!(
+        ")
+           AND EXISTS (SELECT 1 FROM t_pic_word j JOIN t_pic p ON j.id_pic = p.id
+                       WHERE j.id_word = w.id AND p.deleted_ts IS NULL AND j.scope IN ({}))", placeholders(scopes.len())));
+    params.extend(scopes.iter().cloned());
+    return (sql, params);
+
+    fn escape_like(text: &str) -> String {
+        return text.replace('!', "!!").replace('%', "!%").replace('_', "!_");
+    }
+}
+
+// 按字计算的编辑距离
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec!(i + 1);
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    return prev[b.len()];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let query = WordQuery::parse("猫 狗 | 鱼 -鸟");
+        assert_eq!(query.all, vec!(vec!("猫"), vec!("狗", "鱼")));
+        assert_eq!(query.none, vec!("鸟"));
+        assert_eq!(query.words(), vec!("猫", "狗", "鱼"));
+        assert!(WordQuery::parse("-猫").is_empty());
+    }
+
+    #[test]
+    fn matches() {
+        let query = WordQuery::parse("猫 狗|鱼 -鸟");
+        assert!(query.matches(&["猫", "鱼"]));
+        assert!(!query.matches(&["猫"]));
+        assert!(!query.matches(&["猫", "狗", "鸟"]));
+    }
+
+    #[test]
+    fn similar() {
+        let words: Vec<String> = ["猫咪", "小猫咪", "猫", "狗狗", "大狗狗", "小狗"].iter().map(|t| String::from(*t)).collect();
+        assert_eq!(similar_words("猫", &words), vec!("猫咪", "小猫咪"));
+        assert_eq!(similar_words("小狗狗", &words), vec!("大狗狗", "小狗", "狗狗"));
+        assert!(similar_words("鱼", &words).is_empty());
+    }
+
+    #[test]
+    fn candidates() {
+        assert!(maybe_similar("猫", "小猫咪"));
+        // 一个字的词只找包含它的
+        assert!(!maybe_similar("猫", "狗"));
+        assert!(maybe_similar("小狗狗", "小狗"));
+        assert!(!maybe_similar("小狗狗", "猫咪"));
+        // 字数差得太多
+        assert!(!maybe_similar("小狗狗", "狗"));
+        assert!(!maybe_similar("小狗狗", "狗狗狗狗狗"));
+    }
+}
@@ -0,0 +1,70 @@
+// 表结构随程序一起发布，每次升级追加一个新的版本，已经发布的版本不要再修改
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+pub const MYSQL_MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "init", sql: include_str!("../../sql/mysql/001_init.sql") },
+    Migration { version: 2, name: "pic_hash", sql: include_str!("../../sql/mysql/002_pic_hash.sql") },
+    Migration { version: 3, name: "pic_phash", sql: include_str!("../../sql/mysql/003_pic_phash.sql") },
+    Migration { version: 4, name: "pic_word_use", sql: include_str!("../../sql/mysql/004_pic_word_use.sql") },
+    Migration { version: 5, name: "word_forms", sql: include_str!("../../sql/mysql/005_word_forms.sql") },
+    Migration { version: 6, name: "synonym", sql: include_str!("../../sql/mysql/006_synonym.sql") },
+    Migration { version: 7, name: "scope", sql: include_str!("../../sql/mysql/007_scope.sql") },
+    Migration { version: 8, name: "pic_word_tagger", sql: include_str!("../../sql/mysql/008_pic_word_tagger.sql") },
+    Migration { version: 9, name: "pic_trash", sql: include_str!("../../sql/mysql/009_pic_trash.sql") },
+    Migration { version: 10, name: "dict_word", sql: include_str!("../../sql/mysql/010_dict_word.sql") },
+];
+
+pub const SQLITE_MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "init", sql: include_str!("../../sql/sqlite/001_init.sql") },
+    Migration { version: 2, name: "pic_hash", sql: include_str!("../../sql/sqlite/002_pic_hash.sql") },
+    Migration { version: 3, name: "pic_phash", sql: include_str!("../../sql/sqlite/003_pic_phash.sql") },
+    Migration { version: 4, name: "pic_word_use", sql: include_str!("../../sql/sqlite/004_pic_word_use.sql") },
+    Migration { version: 5, name: "word_forms", sql: include_str!("../../sql/sqlite/005_word_forms.sql") },
+    Migration { version: 6, name: "synonym", sql: include_str!("../../sql/sqlite/006_synonym.sql") },
+    Migration { version: 7, name: "scope", sql: include_str!("../../sql/sqlite/007_scope.sql") },
+    Migration { version: 8, name: "pic_word_tagger", sql: include_str!("../../sql/sqlite/008_pic_word_tagger.sql") },
+    Migration { version: 9, name: "pic_trash", sql: include_str!("../../sql/sqlite/009_pic_trash.sql") },
+    Migration { version: 10, name: "dict_word", sql: include_str!("../../sql/sqlite/010_dict_word.sql") },
+];
+
+// 把脚本拆成一条条语句，脚本里的分号只用来分隔语句
+pub fn statements(sql: &str) -> Vec<String> {
+    let sql: Vec<&str> = sql.lines()
+        .filter(|t| !t.trim_start().starts_with("--"))
+        .collect();
+    return sql.join("\n")
+        .split(';')
+        .map(|t| String::from(t.trim()))
+        .filter(|t| !t.is_empty())
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions() {
+        for list in [MYSQL_MIGRATIONS, SQLITE_MIGRATIONS].iter() {
+            for (i, t) in list.iter().enumerate() {
+                assert_eq!(t.version as usize, i + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn split() {
+        let list = statements("-- 注释; 不算\nCREATE TABLE a (id INT);\n\nSET @sql = IF(1, 'DO 0', 'DO 1');  ");
+        assert_eq!(list, vec!("CREATE TABLE a (id INT)", "SET @sql = IF(1, 'DO 0', 'DO 1')"));
+
+        // 三步各自拼出语句再PREPARE执行，最后建t_scope
+        let list = statements(MYSQL_MIGRATIONS[6].sql);
+        assert_eq!(list.len(), 14);
+        assert_eq!(list.iter().filter(|t| t.starts_with("PREPARE")).count(), 3);
+        assert!(list[13].starts_with("CREATE TABLE IF NOT EXISTS t_scope"));
+    }
+}
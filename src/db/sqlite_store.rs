@@ -0,0 +1,1262 @@
+use chrono::DateTime;
+use rusqlite::{Connection, Error, OptionalExtension, NO_PARAMS};
+use rusqlite::types::{FromSql, ToSql};
+use super::{DbError, Dump, Orphans, PicDump, PicStore, PicWordDump, Rank, TagRecord, Tagger, WordQuery};
+use super::normalize::{self, WordForms};
+use super::query::{placeholders, similar_words_sql};
+use super::schema::SQLITE_MIGRATIONS;
+
+#[derive(Debug)]
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    pub fn open(path: &str) -> Result<SqliteStore, DbError> {
+        let conn = Connection::open(path)?;
+        return Ok(SqliteStore { conn });
+    }
+
+    // 在一个事务里执行写操作，任何一步出错都整体回滚
+    fn transaction<T, F>(&mut self, f: F) -> Result<T, DbError>
+        where F: FnOnce(&Connection) -> Result<T, Error>
+    {
+        let tx = self.conn.transaction()?;
+        return match f(&tx) {
+            Ok(t) => {
+                tx.commit()?;
+                Ok(t)
+            }
+            Err(t) => {
+                tx.rollback().unwrap_or(());
+                Err(DbError::from(t))
+            }
+        };
+    }
+}
+
+impl PicStore for SqliteStore {
+    fn append_word(&mut self, scope: &str, pic: &str, word: &str, tagger: &Tagger) -> Result<(), DbError> {
+        return self.transaction(|tx| insert_words(scope, pic, word, tagger, tx));
+    }
+
+    fn replace_word(&mut self, scope: &str, pic: &str, word: &str, tagger: &Tagger) -> Result<(), DbError> {
+        return self.transaction(|tx| {
+            // 只替换词，图片本身的hash和别名都保留
+            remove_words(scope, pic, tx)?;
+            insert_words(scope, pic, word, tagger, tx)
+        });
+    }
+
+    fn remove_word(&mut self, scope: &str, pic: &str, word: &str) -> Result<(), DbError> {
+        return self.transaction(|tx| {
+            for word in word.split_whitespace() {
+                tx.execute_named(
+                    "DELETE FROM t_pic_word
+                     WHERE id_pic = (SELECT id FROM t_pic WHERE name = :pic)
+                       AND id_word = (SELECT id FROM t_word WHERE word = :word)
+                       AND scope = :scope",
+                    &[(":pic", &pic), (":word", &word), (":scope", &scope)])?;
+                tx.execute_named(
+                    "DELETE FROM t_word
+                     WHERE word = :word
+                       AND NOT EXISTS (SELECT 1 FROM t_pic_word j WHERE j.id_word = t_word.id)",
+                    &[(":word", &word)])?;
+            }
+            Ok(())
+        });
+    }
+
+    fn delete_pic(&mut self, pic: &str) -> Result<(), DbError> {
+        self.conn.execute_named(
+            "UPDATE t_pic SET deleted_ts = strftime('%Y-%m-%d %H:%M:%f', 'now') WHERE name = :name AND deleted_ts IS NULL",
+            &[(":name", &pic)])?;
+        return Ok(());
+    }
+
+    fn restore_pic(&mut self, pic: &str) -> Result<bool, DbError> {
+        let count = self.conn.execute_named(
+            "UPDATE t_pic SET deleted_ts = NULL WHERE name = :name AND deleted_ts IS NOT NULL",
+            &[(":name", &pic)])?;
+        return Ok(count > 0);
+    }
+
+    fn list_trash(&mut self) -> Result<Vec<(String, String)>, DbError> {
+        // deleted_ts存的是utc时间，显示成本地时间
+        let mut stmt = self.conn.prepare(
+            "SELECT name, datetime(deleted_ts, 'localtime')
+             FROM t_pic
+             WHERE deleted_ts IS NOT NULL
+             ORDER BY deleted_ts DESC")?;
+        let rows = stmt.query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        let mut list = vec!();
+        for row in rows {
+            list.push(row?);
+        }
+        return Ok(list);
+    }
+
+    fn purge_pic(&mut self, pic: &str) -> Result<bool, DbError> {
+        return self.transaction(|tx| {
+            let trashed: Option<i64> = select_one(tx,
+                "SELECT id FROM t_pic WHERE name = :name AND deleted_ts IS NOT NULL",
+                &[(":name", &pic)])?;
+            if trashed.is_none() {
+                return Ok(false);
+            }
+            remove_pic(pic, tx)?;
+            Ok(true)
+        });
+    }
+
+    fn purge_trash(&mut self, days: u32) -> Result<Vec<String>, DbError> {
+        return self.transaction(|tx| {
+            let pics: Vec<String> = select_list(tx,
+                "SELECT name FROM t_pic WHERE deleted_ts <= strftime('%Y-%m-%d %H:%M:%f', 'now', :offset)",
+                &[(":offset", &format!("-{} days", days))])?;
+            for pic in pics.iter() {
+                remove_pic(pic, tx)?;
+            }
+            Ok(pics)
+        });
+    }
+
+    fn query_pic(&mut self, scopes: &[String], query: &WordQuery, rank: Rank) -> Result<Vec<String>, DbError> {
+        if query.is_empty() || scopes.is_empty() {
+            return Ok(vec!());
+        }
+        let conn = &self.conn;
+
+        // 选出排在最前的一张图片
+        let (sql, params) = query.to_sql(scopes, None, rank.order_by());
+        let pic: Option<String> = conn.query_row(&sql, &params, |row| row.get(0)).optional()?;
+        if pic.is_none() {
+            return Ok(vec!());
+        }
+        let pic = pic.unwrap();
+
+        // 以及随机的一张图片
+        let (sql, params) = query.to_sql(scopes, Some(&pic), "random()");
+        let pic2: Option<String> = conn.query_row(&sql, &params, |row| row.get(0)).optional()?;
+        return match pic2 {
+            Some(t) => Ok(vec!(pic, t)),
+            None => Ok(vec!(pic))
+        };
+    }
+
+    fn touch_pic(&mut self, scopes: &[String], pic: &str, word: &str) -> Result<(), DbError> {
+        let mut params = vec!(String::from(pic), String::from(word));
+        params.extend(scopes.iter().cloned());
+
+        // CURRENT_TIMESTAMP只精确到秒，last_ts都写成精确到毫秒的时间
+        self.conn.execute(
+            &format!("UPDATE t_pic_word
+                      SET last_ts = strftime('%Y-%m-%d %H:%M:%f', 'now'), use_count = use_count + 1
+                      WHERE id_pic = (SELECT id FROM t_pic WHERE name = ?)
+                        AND id_word = (SELECT id FROM t_word WHERE word = ?)
+                        AND scope IN ({})", placeholders(scopes.len())),
+            &params)?;
+        return Ok(());
+    }
+
+    fn random_pic(&mut self, scopes: &[String]) -> Result<String, DbError> {
+        let pic: Option<String> = self.conn.query_row(
+            &format!("SELECT p.name
+                      FROM t_pic p
+                      WHERE p.deleted_ts IS NULL
+                        AND EXISTS (SELECT 1 FROM t_pic_word j WHERE j.id_pic = p.id AND j.scope IN ({}))
+                      ORDER BY random()
+                      LIMIT 1", placeholders(scopes.len())),
+            scopes,
+            |row| row.get(0)).optional()?;
+
+        return match pic {
+            Some(t) => Ok(t),
+            None => Ok(String::new())
+        };
+    }
+
+    fn list_pic_words(&mut self, pic: &str) -> Result<String, DbError> {
+        let words: Vec<String> = select_list(&self.conn,
+            "SELECT word
+             FROM t_pic p
+             JOIN t_pic_word j ON j.id_pic = p.id
+             JOIN t_word w ON j.id_word = w.id
+             WHERE p.name = :pic",
+            &[(":pic", &pic)])?;
+
+        let words = words.join(" ");
+        return Ok(words);
+    }
+
+    fn list_pic_tags(&mut self, pic: &str) -> Result<Vec<TagRecord>, DbError> {
+        // created_ts存的是utc时间，显示成本地时间
+        let mut stmt = self.conn.prepare(
+            "SELECT w.word, j.scope, j.tagger_id, j.group_id, datetime(j.created_ts, 'localtime')
+             FROM t_pic p
+             JOIN t_pic_word j ON j.id_pic = p.id
+             JOIN t_word w ON j.id_word = w.id
+             WHERE p.name = :pic
+             ORDER BY j.created_ts IS NULL DESC, j.created_ts, j.id")?;
+        let rows = stmt.query_map_named(&[(":pic", &pic)], |row| Ok(TagRecord {
+            word: row.get(0)?,
+            scope: row.get(1)?,
+            tagger: Tagger { user_id: row.get(2)?, group_id: row.get(3)? },
+            created_ts: row.get(4)?,
+        }))?;
+
+        let mut list = vec!();
+        for row in rows {
+            list.push(row?);
+        }
+        return Ok(list);
+    }
+
+    fn count_pic(&mut self) -> Result<u64, DbError> {
+        let pic_count: i64 = select_one(&self.conn,
+            "SELECT count(1) FROM t_pic WHERE deleted_ts IS NULL",
+            &[])?.unwrap_or(0);
+        return Ok(pic_count as u64);
+    }
+
+    fn list_pics(&mut self) -> Result<Vec<String>, DbError> {
+        let pics = select_list(&self.conn,
+            "SELECT name FROM t_pic",
+            &[])?;
+        return Ok(pics);
+    }
+
+    fn find_words_by_forms(&mut self, word: &str) -> Result<Vec<String>, DbError> {
+        let (simplified, pinyin) = normalize::query_forms(word);
+        let words = select_list(&self.conn,
+            "SELECT word FROM t_word WHERE simplified = :simplified OR pinyin = :pinyin OR initials = :pinyin",
+            &[(":simplified", &simplified), (":pinyin", &pinyin)])?;
+        return Ok(words);
+    }
+
+    fn update_word_forms(&mut self) -> Result<u64, DbError> {
+        let words: Vec<String> = select_list(&self.conn,
+            "SELECT word FROM t_word WHERE pinyin IS NULL",
+            &[])?;
+        self.transaction(|conn| {
+            for word in words.iter() {
+                let forms = WordForms::of(word);
+                conn.execute_named(
+                    "UPDATE t_word SET simplified = :simplified, pinyin = :pinyin, initials = :initials WHERE word = :word",
+                    &[(":simplified", &forms.simplified), (":pinyin", &forms.pinyin), (":initials", &forms.initials), (":word", word)])?;
+            }
+            Ok(())
+        })?;
+        return Ok(words.len() as u64);
+    }
+
+    fn add_synonyms(&mut self, words: &[&str]) -> Result<(), DbError> {
+        return self.transaction(|conn| insert_synonyms(words, conn));
+    }
+
+    fn remove_synonym(&mut self, word: &str) -> Result<bool, DbError> {
+        return self.transaction(|conn| remove_synonym(word, conn));
+    }
+
+    fn list_synonyms(&mut self) -> Result<Vec<Vec<String>>, DbError> {
+        let mut stmt = self.conn.prepare("SELECT id_group, word FROM t_synonym ORDER BY id_group, id")?;
+        let rows = stmt.query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        let mut groups: Vec<(i64, Vec<String>)> = vec!();
+        for row in rows {
+            let (group, word) = row?;
+            match groups.last_mut() {
+                Some(t) if t.0 == group => t.1.push(word),
+                _ => groups.push((group, vec!(word)))
+            }
+        }
+        return Ok(groups.into_iter().map(|t| t.1).collect());
+    }
+
+    fn find_synonyms(&mut self, word: &str) -> Result<Vec<String>, DbError> {
+        let words = select_list(&self.conn,
+            "SELECT s2.word
+             FROM t_synonym s1
+             JOIN t_synonym s2 ON s2.id_group = s1.id_group
+             WHERE s1.word = :word
+               AND s2.word != :word",
+            &[(":word", &word)])?;
+        return Ok(words);
+    }
+
+    fn add_dict_words(&mut self, words: &[&str]) -> Result<(), DbError> {
+        return self.transaction(|tx| insert_dict_words(words, tx));
+    }
+
+    fn remove_dict_word(&mut self, word: &str) -> Result<bool, DbError> {
+        let count = self.conn.execute_named("DELETE FROM t_dict_word WHERE word = :word", &[(":word", &word)])?;
+        return Ok(count > 0);
+    }
+
+    fn list_dict_words(&mut self) -> Result<Vec<String>, DbError> {
+        let mut stmt = self.conn.prepare("SELECT word FROM t_dict_word ORDER BY id")?;
+        let rows = stmt.query_map(NO_PARAMS, |row| row.get(0))?;
+
+        let mut words = vec!();
+        for row in rows {
+            words.push(row?);
+        }
+        return Ok(words);
+    }
+
+    fn list_similar_words(&mut self, scopes: &[String], word: &str) -> Result<Vec<String>, DbError> {
+        let (sql, params) = similar_words_sql(scopes, word, "length");
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(&params, |row| row.get(0))?;
+
+        let mut words = vec!();
+        for row in rows {
+            words.push(row?);
+        }
+        return Ok(words);
+    }
+
+    fn search_global(&mut self, scope: &str) -> Result<bool, DbError> {
+        let search_global: Option<bool> = select_one(&self.conn,
+            "SELECT search_global FROM t_scope WHERE scope = :scope",
+            &[(":scope", &scope)])?;
+        return Ok(search_global.unwrap_or(true));
+    }
+
+    fn set_search_global(&mut self, scope: &str, search_global: bool) -> Result<(), DbError> {
+        self.conn.execute_named(
+            "INSERT OR REPLACE INTO t_scope (scope, search_global) VALUES (:scope, :search_global)",
+            &[(":scope", &scope), (":search_global", &search_global)])?;
+        return Ok(());
+    }
+
+    fn find_orphans(&mut self) -> Result<Orphans, DbError> {
+        let conn = &self.conn;
+
+        let pics = select_list(conn,
+            "SELECT p.name
+             FROM t_pic p
+             LEFT JOIN t_pic_word j ON j.id_pic = p.id
+             WHERE j.id IS NULL",
+            &[])?;
+        let words = select_list(conn,
+            "SELECT w.word
+             FROM t_word w
+             LEFT JOIN t_pic_word j ON j.id_word = w.id
+             WHERE j.id IS NULL",
+            &[])?;
+        let pic_words: i64 = select_one(conn,
+            "SELECT count(1)
+             FROM t_pic_word j
+             LEFT JOIN t_pic p ON j.id_pic = p.id
+             LEFT JOIN t_word w ON j.id_word = w.id
+             WHERE p.id IS NULL OR w.id IS NULL",
+            &[])?.unwrap_or(0);
+        return Ok(Orphans { pics, words, pic_words: pic_words as u64 });
+    }
+
+    fn remove_orphans(&mut self, orphans: &Orphans) -> Result<(), DbError> {
+        return self.transaction(|tx| {
+            if orphans.pic_words > 0 {
+                tx.execute(
+                    "DELETE FROM t_pic_word
+                     WHERE id_pic NOT IN (SELECT id FROM t_pic)
+                        OR id_word NOT IN (SELECT id FROM t_word)",
+                    NO_PARAMS)?;
+            }
+            for pic in orphans.pics.iter() {
+                tx.execute_named(
+                    "DELETE FROM t_pic
+                     WHERE name = :name
+                       AND NOT EXISTS (SELECT 1 FROM t_pic_word j WHERE j.id_pic = t_pic.id)",
+                    &[(":name", pic)])?;
+            }
+            for word in orphans.words.iter() {
+                tx.execute_named(
+                    "DELETE FROM t_word
+                     WHERE word = :word
+                       AND NOT EXISTS (SELECT 1 FROM t_pic_word j WHERE j.id_word = t_word.id)",
+                    &[(":word", word)])?;
+            }
+            Ok(())
+        });
+    }
+
+    fn resolve_pic(&mut self, pic: &str) -> Result<String, DbError> {
+        let name: Option<String> = select_one(&self.conn,
+            "SELECT p.name
+             FROM t_pic_alias a
+             JOIN t_pic p ON a.id_pic = p.id
+             WHERE a.name = :name",
+            &[(":name", &pic)])?;
+        return Ok(name.unwrap_or_else(|| String::from(pic)));
+    }
+
+    fn find_pic_by_hash(&mut self, pic: &str, hash: &str) -> Result<Option<String>, DbError> {
+        let name = select_one(&self.conn,
+            "SELECT name FROM t_pic WHERE hash = :hash AND name <> :name AND deleted_ts IS NULL LIMIT 1",
+            &[(":hash", &hash), (":name", &pic)])?;
+        return Ok(name);
+    }
+
+    fn set_pic_hash(&mut self, pic: &str, hash: &str) -> Result<(), DbError> {
+        self.conn.execute_named(
+            "UPDATE t_pic SET hash = :hash WHERE name = :name",
+            &[(":hash", &hash), (":name", &pic)])?;
+        return Ok(());
+    }
+
+    fn list_unhashed_pics(&mut self) -> Result<Vec<String>, DbError> {
+        let pics = select_list(&self.conn,
+            "SELECT name FROM t_pic WHERE hash IS NULL",
+            &[])?;
+        return Ok(pics);
+    }
+
+    fn append_word_dedup(&mut self, scope: &str, pic: &str, word: &str, tagger: &Tagger, hash: Option<&str>) -> Result<Option<String>, DbError> {
+        return self.transaction(|tx| {
+            insert_words(scope, pic, word, tagger, tx)?;
+            match hash {
+                Some(t) => dedup_by_hash(pic, t, tx),
+                None => Ok(None)
+            }
+        });
+    }
+
+    fn dedup_pic(&mut self, pic: &str, hash: &str) -> Result<Option<String>, DbError> {
+        return self.transaction(|tx| dedup_by_hash(pic, hash, tx));
+    }
+
+    fn set_pic_phash(&mut self, pic: &str, phash: u64) -> Result<(), DbError> {
+        // sqlite只有有符号整数，按位原样存进去
+        self.conn.execute_named(
+            "UPDATE t_pic SET phash = :phash WHERE name = :name",
+            &[(":phash", &(phash as i64)), (":name", &pic)])?;
+        return Ok(());
+    }
+
+    fn list_pic_phashes(&mut self) -> Result<Vec<(String, Option<u64>)>, DbError> {
+        let mut stmt = self.conn.prepare("SELECT name, phash FROM t_pic WHERE deleted_ts IS NULL")?;
+        let rows = stmt.query_map(NO_PARAMS, |row| {
+            let phash: Option<i64> = row.get(1)?;
+            Ok((row.get(0)?, phash.map(|t| t as u64)))
+        })?;
+
+        let mut list = vec!();
+        for row in rows {
+            list.push(row?);
+        }
+        return Ok(list);
+    }
+
+    fn merge_pic(&mut self, from: &str, into: &str) -> Result<(), DbError> {
+        if from == into {
+            return Ok(());
+        }
+
+        return self.transaction(|tx| merge_pics(from, into, tx));
+    }
+
+    fn list_pic_links(&mut self, pic: Option<&str>) -> Result<Vec<PicWordDump>, DbError> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT p.name, w.word, j.scope, j.tagger_id, j.group_id, {}, {}, j.use_count
+             FROM t_pic_word j
+             JOIN t_pic p ON j.id_pic = p.id
+             JOIN t_word w ON j.id_word = w.id
+             WHERE p.deleted_ts IS NULL
+               AND (:pic IS NULL OR p.name = :pic)
+             ORDER BY j.id", millis("j.created_ts"), millis("j.last_ts")))?;
+        let rows = stmt.query_map_named(&[(":pic", &pic)], |row| {
+            let use_count: i64 = row.get(7)?;
+            Ok(PicWordDump {
+                pic: row.get(0)?,
+                word: row.get(1)?,
+                scope: row.get(2)?,
+                tagger: Tagger { user_id: row.get(3)?, group_id: row.get(4)? },
+                created_ts: row.get(5)?,
+                last_ts: row.get(6)?,
+                use_count: use_count as u64,
+            })
+        })?;
+
+        let mut links = vec!();
+        for row in rows {
+            links.push(row?);
+        }
+        return Ok(links);
+    }
+
+    fn export(&mut self) -> Result<Dump, DbError> {
+        let mut dump = Dump::default();
+        {
+            let conn = &self.conn;
+
+            let mut stmt = conn.prepare(&format!(
+                "SELECT name, hash, phash, {} FROM t_pic ORDER BY id", millis("deleted_ts")))?;
+            let rows = stmt.query_map(NO_PARAMS, |row| {
+                let phash: Option<i64> = row.get(2)?;
+                Ok(PicDump { name: row.get(0)?, hash: row.get(1)?, phash: phash.map(|t| t as u64), deleted_ts: row.get(3)? })
+            })?;
+            for row in rows {
+                dump.pics.push(row?);
+            }
+
+            let mut stmt = conn.prepare(&format!(
+                "SELECT p.name, w.word, j.scope, j.tagger_id, j.group_id, {}, {}, j.use_count
+                 FROM t_pic_word j
+                 JOIN t_pic p ON j.id_pic = p.id
+                 JOIN t_word w ON j.id_word = w.id
+                 ORDER BY j.id", millis("j.created_ts"), millis("j.last_ts")))?;
+            let rows = stmt.query_map(NO_PARAMS, |row| {
+                let use_count: i64 = row.get(7)?;
+                Ok(PicWordDump {
+                    pic: row.get(0)?,
+                    word: row.get(1)?,
+                    scope: row.get(2)?,
+                    tagger: Tagger { user_id: row.get(3)?, group_id: row.get(4)? },
+                    created_ts: row.get(5)?,
+                    last_ts: row.get(6)?,
+                    use_count: use_count as u64,
+                })
+            })?;
+            for row in rows {
+                dump.pic_words.push(row?);
+            }
+
+            let mut stmt = conn.prepare(
+                "SELECT a.name, p.name FROM t_pic_alias a JOIN t_pic p ON a.id_pic = p.id ORDER BY a.id")?;
+            let rows = stmt.query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?;
+            for row in rows {
+                dump.aliases.push(row?);
+            }
+
+            let mut stmt = conn.prepare("SELECT scope, search_global FROM t_scope ORDER BY scope")?;
+            let rows = stmt.query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?;
+            for row in rows {
+                dump.scopes.push(row?);
+            }
+        }
+        dump.synonyms = self.list_synonyms()?;
+        dump.dict_words = self.list_dict_words()?;
+        return Ok(dump);
+    }
+
+    fn import(&mut self, dump: &Dump) -> Result<(), DbError> {
+        return self.transaction(|tx| {
+            for pic in dump.pics.iter() {
+                let phash = pic.phash.map(|t| t as i64);
+                tx.execute_named(
+                    "INSERT OR IGNORE INTO t_pic (name, hash, phash, deleted_ts)
+                     VALUES (:name, :hash, :phash, :deleted_ts)",
+                    &[(":name", &pic.name), (":hash", &pic.hash), (":phash", &phash), (":deleted_ts", &from_millis(pic.deleted_ts))])?;
+                tx.execute_named(
+                    "UPDATE t_pic SET hash = COALESCE(hash, :hash), phash = COALESCE(phash, :phash) WHERE name = :name",
+                    &[(":name", &pic.name), (":hash", &pic.hash), (":phash", &phash)])?;
+            }
+
+            for t in dump.pic_words.iter() {
+                let pic_id = find_or_insert_pic(&t.pic, tx)?;
+                let word_id = find_or_insert_word(&t.word, tx)?;
+                let use_count = t.use_count as i64;
+                let (created_ts, last_ts) = (from_millis(t.created_ts), from_millis(Some(t.last_ts)));
+
+                let assoc_id: Option<i64> = select_one(tx,
+                    "SELECT id FROM t_pic_word WHERE id_pic = :pic_id AND id_word = :word_id AND scope = :scope",
+                    &[(":pic_id", &pic_id), (":word_id", &word_id), (":scope", &t.scope)])?;
+                match assoc_id {
+                    Some(id) => tx.execute_named(
+                        "UPDATE t_pic_word
+                         SET last_ts = MAX(last_ts, :last_ts),
+                             use_count = MAX(use_count, :use_count)
+                         WHERE id = :id",
+                        &[(":last_ts", &last_ts), (":use_count", &use_count), (":id", &id)])?,
+                    None => tx.execute_named(
+                        "INSERT INTO t_pic_word (id_pic, id_word, scope, tagger_id, group_id, created_ts, last_ts, use_count)
+                         VALUES (:pic_id, :word_id, :scope, :tagger_id, :group_id,
+                                 :created_ts,
+                                 :last_ts, :use_count)",
+                        &[(":pic_id", &pic_id), (":word_id", &word_id), (":scope", &t.scope),
+                          (":tagger_id", &t.tagger.user_id), (":group_id", &t.tagger.group_id),
+                          (":created_ts", &created_ts), (":last_ts", &last_ts), (":use_count", &use_count)])?
+                };
+            }
+
+            // 别名只在还没有同名图片时才加上
+            for (name, pic) in dump.aliases.iter() {
+                tx.execute_named(
+                    "INSERT OR IGNORE INTO t_pic_alias (name, id_pic)
+                     SELECT :name, id FROM t_pic
+                     WHERE name = :pic
+                       AND NOT EXISTS (SELECT 1 FROM t_pic WHERE name = :name)",
+                    &[(":name", name), (":pic", pic)])?;
+            }
+
+            for words in dump.synonyms.iter() {
+                let words: Vec<&str> = words.iter().map(|t| t.as_str()).collect();
+                insert_synonyms(&words, tx)?;
+            }
+
+            // 已经设置过的范围保留原来的设置
+            for (scope, search_global) in dump.scopes.iter() {
+                tx.execute_named(
+                    "INSERT OR IGNORE INTO t_scope (scope, search_global) VALUES (:scope, :search_global)",
+                    &[(":scope", scope), (":search_global", search_global)])?;
+            }
+
+            let words: Vec<&str> = dump.dict_words.iter().map(|t| t.as_str()).collect();
+            insert_dict_words(&words, tx)?;
+            Ok(())
+        });
+    }
+
+    fn migrate(&mut self) -> Result<Vec<String>, DbError> {
+        self.conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS t_schema_version (
+                 version INTEGER PRIMARY KEY,
+                 name TEXT NOT NULL,
+                 applied_ts TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+             )")?;
+        let current: u32 = select_one(&self.conn,
+            "SELECT version FROM t_schema_version ORDER BY version DESC LIMIT 1",
+            &[])?.unwrap_or(0);
+
+        // sqlite的DDL可以回滚，每个版本在一个事务里执行
+        let mut applied = vec!();
+        for migration in SQLITE_MIGRATIONS.iter().filter(|t| t.version > current) {
+            self.transaction(|tx| {
+                tx.execute_batch(migration.sql)?;
+                tx.execute_named(
+                    "INSERT INTO t_schema_version (version, name) VALUES (:version, :name)",
+                    &[(":version", &migration.version), (":name", &migration.name)])?;
+                Ok(())
+            })?;
+            applied.push(format!("{} {}", migration.version, migration.name));
+        }
+        return Ok(applied);
+    }
+}
+
+// hash相同的图片已经存在时把pic合并过去并返回它，否则记下pic的hash
+fn dedup_by_hash(pic: &str, hash: &str, conn: &Connection) -> Result<Option<String>, Error> {
+    let same: Option<String> = select_one(conn,
+        "SELECT name FROM t_pic WHERE hash = :hash AND name <> :name AND deleted_ts IS NULL LIMIT 1",
+        &[(":hash", &hash), (":name", &pic)])?;
+    if let Some(t) = same {
+        merge_pics(pic, &t, conn)?;
+        return Ok(Some(t));
+    }
+
+    conn.execute_named(
+        "UPDATE t_pic SET hash = :hash WHERE name = :name",
+        &[(":hash", &hash), (":name", &pic)])?;
+    return Ok(None);
+}
+
+// 把from的词都追加到into上，删掉from，from之后作为into的别名
+fn merge_pics(from: &str, into: &str, conn: &Connection) -> Result<(), Error> {
+    let from_id = match find_pic_id_by_pic(from, conn)? {
+        Some(t) => t,
+        None => return Ok(())
+    };
+    let into_id = find_or_insert_pic(into, conn)?;
+
+    // 词的合并与append_word走同一条路径，每个词在原来的范围里合并，仍然记在原来加它的人名下
+    let mut stmt = conn.prepare(
+        "SELECT j.scope, w.word, j.tagger_id, j.group_id
+         FROM t_pic_word j
+         JOIN t_word w ON j.id_word = w.id
+         WHERE j.id_pic = :pic_id
+         ORDER BY j.id")?;
+    let rows = stmt.query_map_named(&[(":pic_id", &from_id)], |row| {
+        let scope: String = row.get(0)?;
+        let word: String = row.get(1)?;
+        Ok((scope, word, Tagger { user_id: row.get(2)?, group_id: row.get(3)? }))
+    })?;
+    let mut words = vec!();
+    for row in rows {
+        words.push(row?);
+    }
+    for (scope, word, tagger) in words.iter() {
+        insert_words(scope, into, word, tagger, conn)?;
+    }
+
+    conn.execute_named(
+        "DELETE FROM t_pic_word WHERE id_pic = :from_id",
+        &[(":from_id", &from_id)])?;
+    conn.execute_named(
+        "UPDATE t_pic_alias SET id_pic = :into_id WHERE id_pic = :from_id",
+        &[(":into_id", &into_id), (":from_id", &from_id)])?;
+    conn.execute_named(
+        "DELETE FROM t_pic WHERE id = :from_id",
+        &[(":from_id", &from_id)])?;
+    conn.execute_named(
+        "INSERT INTO t_pic_alias (name, id_pic) VALUES (:name, :into_id)",
+        &[(":name", &from), (":into_id", &into_id)])?;
+    return Ok(());
+}
+
+fn insert_words(scope: &str, pic: &str, word: &str, tagger: &Tagger, conn: &Connection) -> Result<(), Error> {
+    let pic_id = find_or_insert_pic(pic, conn)?;
+    // 又有人set了，说明这张图片还有用
+    conn.execute_named(
+        "UPDATE t_pic SET deleted_ts = NULL WHERE id = :pic_id AND deleted_ts IS NOT NULL",
+        &[(":pic_id", &pic_id)])?;
+
+    let words = word.split_whitespace();
+    for word in words {
+        let word_id = find_or_insert_word(word, conn)?;
+
+        let assoc_id: Option<i64> = select_one(conn,
+            "SELECT id FROM t_pic_word WHERE id_pic = :pic_id AND id_word = :word_id AND scope = :scope",
+            &[(":pic_id", &pic_id), (":word_id", &word_id), (":scope", &scope)])?;
+        match assoc_id {
+            // 重复set也算是最近用过
+            Some(t) => conn.execute_named(
+                "UPDATE t_pic_word SET last_ts = strftime('%Y-%m-%d %H:%M:%f', 'now') WHERE id = :id",
+                &[(":id", &t)])?,
+            None => conn.execute_named(
+                "INSERT INTO t_pic_word (id_pic, id_word, scope, tagger_id, group_id, last_ts, created_ts)
+                 VALUES (:pic_id, :word_id, :scope, :tagger_id, :group_id, strftime('%Y-%m-%d %H:%M:%f', 'now'), strftime('%Y-%m-%d %H:%M:%f', 'now'))",
+                &[(":pic_id", &pic_id), (":word_id", &word_id), (":scope", &scope), (":tagger_id", &tagger.user_id), (":group_id", &tagger.group_id)])?
+        };
+    }
+
+    return Ok(());
+}
+
+fn insert_synonyms(words: &[&str], conn: &Connection) -> Result<(), Error> {
+    // 这些词已经所在的组都并到第一组里，都还没有组的话新建一组
+    let mut groups: Vec<i64> = vec!();
+    for word in words {
+        let group = select_one(conn,
+            "SELECT id_group FROM t_synonym WHERE word = :word",
+            &[(":word", word)])?;
+        if let Some(t) = group {
+            if !groups.contains(&t) {
+                groups.push(t);
+            }
+        }
+    }
+    let group = match groups.first() {
+        Some(t) => *t,
+        None => select_one(conn,
+            "SELECT COALESCE(MAX(id_group), 0) + 1 FROM t_synonym",
+            &[])?.unwrap_or(1)
+    };
+    for t in groups.iter().skip(1) {
+        conn.execute_named(
+            "UPDATE t_synonym SET id_group = :group WHERE id_group = :old",
+            &[(":group", &group), (":old", t)])?;
+    }
+
+    for word in words {
+        conn.execute_named(
+            "INSERT OR IGNORE INTO t_synonym (id_group, word) VALUES (:group, :word)",
+            &[(":group", &group), (":word", word)])?;
+    }
+    return Ok(());
+}
+
+fn insert_dict_words(words: &[&str], conn: &Connection) -> Result<(), Error> {
+    for word in words {
+        conn.execute_named("INSERT OR IGNORE INTO t_dict_word (word) VALUES (:word)", &[(":word", word)])?;
+    }
+    return Ok(());
+}
+
+fn remove_synonym(word: &str, conn: &Connection) -> Result<bool, Error> {
+    let group: Option<i64> = select_one(conn,
+        "SELECT id_group FROM t_synonym WHERE word = :word",
+        &[(":word", &word)])?;
+    let group = match group {
+        Some(t) => t,
+        None => return Ok(false)
+    };
+    conn.execute_named(
+        "DELETE FROM t_synonym WHERE word = :word",
+        &[(":word", &word)])?;
+
+    // 只剩一个词的组没有意义了
+    let count: i64 = select_one(conn,
+        "SELECT count(1) FROM t_synonym WHERE id_group = :group",
+        &[(":group", &group)])?.unwrap_or(0);
+    if count < 2 {
+        conn.execute_named(
+            "DELETE FROM t_synonym WHERE id_group = :group",
+            &[(":group", &group)])?;
+    }
+    return Ok(true);
+}
+
+fn remove_words(scope: &str, pic: &str, conn: &Connection) -> Result<(), Error> {
+    let pic_id = find_pic_id_by_pic(pic, conn)?;
+    if let Some(t) = pic_id {
+        conn.execute_named(
+            "DELETE FROM t_pic_word WHERE id_pic = :pic_id AND scope = :scope",
+            &[(":pic_id", &t), (":scope", &scope)])?;
+    }
+    return Ok(());
+}
+
+fn remove_pic(pic: &str, conn: &Connection) -> Result<(), Error> {
+    let pic_id = find_pic_id_by_pic(pic, conn)?;
+    return match pic_id {
+        Some(t) => {
+            // 先删关联和别名再删图片，避免留下指向不存在图片的数据
+            let word_ids: Vec<i64> = select_list(conn,
+                "SELECT id_word FROM t_pic_word WHERE id_pic = :pic_id",
+                &[(":pic_id", &t)])?;
+            conn.execute_named(
+                "DELETE FROM t_pic_word WHERE id_pic = :pic_id",
+                &[(":pic_id", &t)])?;
+            for word_id in word_ids {
+                conn.execute_named(
+                    "DELETE FROM t_word
+                     WHERE id = :word_id
+                       AND NOT EXISTS (SELECT 1 FROM t_pic_word j WHERE j.id_word = t_word.id)",
+                    &[(":word_id", &word_id)])?;
+            }
+            conn.execute_named(
+                "DELETE FROM t_pic_alias WHERE id_pic = :pic_id",
+                &[(":pic_id", &t)])?;
+            conn.execute_named(
+                "DELETE FROM t_pic WHERE id = :pic_id",
+                &[(":pic_id", &t)])?;
+            Ok(())
+        }
+        None => Ok(())
+    };
+}
+
+fn find_or_insert_pic(pic: &str, conn: &Connection) -> Result<i64, Error> {
+    let pic_id = find_pic_id_by_pic(pic, conn)?;
+    return match pic_id {
+        Some(t) => Ok(t),
+        None => {
+            conn.execute_named(
+                "INSERT INTO t_pic (name) VALUES (:name)",
+                &[(":name", &pic)])?;
+            Ok(conn.last_insert_rowid())
+        }
+    };
+}
+
+fn find_or_insert_word(word: &str, conn: &Connection) -> Result<i64, Error> {
+    let word_id = find_word_id_by_word(word, conn)?;
+    return match word_id {
+        Some(t) => Ok(t),
+        None => {
+            let forms = WordForms::of(word);
+            conn.execute_named(
+                "INSERT INTO t_word (word, simplified, pinyin, initials) VALUES (:word, :simplified, :pinyin, :initials)",
+                &[(":word", &word), (":simplified", &forms.simplified), (":pinyin", &forms.pinyin), (":initials", &forms.initials)])?;
+            Ok(conn.last_insert_rowid())
+        }
+    };
+}
+
+fn find_pic_id_by_pic(pic: &str, conn: &Connection) -> Result<Option<i64>, Error> {
+    return select_one(conn,
+        "SELECT id FROM t_pic WHERE name = :name",
+        &[(":name", &pic)]);
+}
+
+fn find_word_id_by_word(word: &str, conn: &Connection) -> Result<Option<i64>, Error> {
+    return select_one(conn,
+        "SELECT id FROM t_word WHERE word = :word",
+        &[(":word", &word)]);
+}
+
+// 时间列转成毫秒时间戳的sql，NULL仍然是NULL
+fn millis(column: &str) -> String {
+    return format!("CAST(ROUND((julianday({}) - 2440587.5) * 86400000) AS INTEGER)", column);
+}
+
+// 毫秒时间戳转成时间列的文本，不经过浮点数，免得差一毫秒
+fn from_millis(millis: Option<i64>) -> Option<String> {
+    return millis.and_then(DateTime::from_timestamp_millis).map(|t| t.format("%Y-%m-%d %H:%M:%S%.3f").to_string());
+}
+
+fn select_one<T>(conn: &Connection, sql: &str, params: &[(&str, &dyn ToSql)]) -> Result<Option<T>, Error>
+    where T: FromSql
+{
+    return conn.query_row_named(sql, params, |row| row.get(0)).optional();
+}
+
+fn select_list<T>(conn: &Connection, sql: &str, params: &[(&str, &dyn ToSql)]) -> Result<Vec<T>, Error>
+    where T: FromSql
+{
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map_named(params, |row| row.get(0))?;
+
+    let mut list = vec!();
+    for row in rows {
+        list.push(row?);
+    }
+    return Ok(list);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{group_scope, user_scope, GLOBAL_SCOPE};
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::Duration;
+    use std::{env, fs, process};
+
+    // 测试是并行跑的，每个测试用自己的数据库文件，结束时删掉
+    struct TempDb {
+        path: PathBuf,
+        store: SqliteStore,
+    }
+
+    impl TempDb {
+        fn new(name: &str) -> TempDb {
+            let mut db = TempDb::empty(name);
+            assert_eq!(db.store.migrate().unwrap().len(), SQLITE_MIGRATIONS.len());
+            return db;
+        }
+
+        // 还没有建表的空数据库
+        fn empty(name: &str) -> TempDb {
+            let path = env::temp_dir().join(format!("tutuv3-{}-{}.db", name, process::id()));
+            let _ = fs::remove_file(&path);
+            let store = SqliteStore::open(path.to_str().unwrap()).unwrap();
+            return TempDb { path, store };
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    fn global() -> Vec<String> {
+        return vec!(String::from(GLOBAL_SCOPE));
+    }
+
+    #[test]
+    fn migrate_all_versions() {
+        let db = TempDb::new("migrate");
+        let mut store = SqliteStore::open(db.path.to_str().unwrap()).unwrap();
+        assert!(store.migrate().unwrap().is_empty());
+
+        let versions: Vec<u32> = select_list(&store.conn, "SELECT version FROM t_schema_version ORDER BY version", &[]).unwrap();
+        let expected: Vec<u32> = SQLITE_MIGRATIONS.iter().map(|t| t.version).collect();
+        assert_eq!(versions, expected);
+    }
+
+    #[test]
+    fn upgrade_to_scope() {
+        // 按第6版的表结构准备好数据，再升级上去
+        let mut db = TempDb::empty("upgrade-scope");
+        db.store.conn.execute_batch(
+            "CREATE TABLE t_schema_version (version INTEGER PRIMARY KEY, name TEXT NOT NULL, applied_ts TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP)").unwrap();
+        for t in SQLITE_MIGRATIONS[..6].iter() {
+            db.store.conn.execute_batch(t.sql).unwrap();
+            db.store.conn.execute_named("INSERT INTO t_schema_version (version, name) VALUES (:version, :name)",
+                &[(":version", &t.version), (":name", &t.name)]).unwrap();
+        }
+        db.store.conn.execute_batch(
+            "INSERT INTO t_pic (name) VALUES ('a');
+             INSERT INTO t_word (word) VALUES ('猫');
+             INSERT INTO t_pic_word (id_pic, id_word, use_count) VALUES (1, 1, 3);").unwrap();
+
+        assert_eq!(db.store.migrate().unwrap()[0], "7 scope");
+        // 已有的关联都归到全局图库里，群里默认也查全局图库
+        let query = WordQuery::parse("猫");
+        assert_eq!(db.store.query_pic(&global(), &query, Rank::Newest).unwrap(), vec!("a"));
+        let group = vec!(group_scope("1"));
+        assert!(db.store.query_pic(&group, &query, Rank::Newest).unwrap().is_empty());
+        let use_count: Option<i64> = select_one(&db.store.conn, "SELECT use_count FROM t_pic_word", &[]).unwrap();
+        assert_eq!(use_count, Some(3));
+
+        // 同一张图片同一个词可以分别放在不同的范围里
+        db.store.append_word(&group_scope("1"), "a", "猫", &Tagger::default()).unwrap();
+        assert_eq!(db.store.query_pic(&group, &query, Rank::Newest).unwrap(), vec!("a"));
+    }
+
+    #[test]
+    fn tags() {
+        let mut db = TempDb::new("tags");
+        let tagger = Tagger { user_id: String::from("2"), group_id: String::from("1") };
+        db.store.append_word(&group_scope("1"), "a", "猫", &tagger).unwrap();
+        db.store.append_word(GLOBAL_SCOPE, "b", "狗", &Tagger::default()).unwrap();
+        let tags = db.store.list_pic_tags("a").unwrap();
+        assert_eq!(tags.len(), 1);
+        assert_eq!((tags[0].word.as_str(), tags[0].scope.as_str()), ("猫", "g:1"));
+        assert_eq!((tags[0].tagger.user_id.as_str(), tags[0].tagger.group_id.as_str()), ("2", "1"));
+        assert!(tags[0].created_ts.is_some());
+
+        // 合并后词仍然记在原来加它的人名下
+        db.store.merge_pic("a", "b").unwrap();
+        let tags = db.store.list_pic_tags("b").unwrap();
+        let cat = tags.iter().find(|t| t.word == "猫").unwrap();
+        assert_eq!((cat.scope.as_str(), cat.tagger.user_id.as_str()), ("g:1", "2"));
+    }
+
+    #[test]
+    fn remove_word() {
+        let mut db = TempDb::new("remove-word");
+        db.store.append_word(GLOBAL_SCOPE, "a", "猫 狗 鱼", &Tagger::default()).unwrap();
+        db.store.append_word(&group_scope("1"), "a", "狗", &Tagger::default()).unwrap();
+        // 只去掉scope里的，没有的词忽略
+        db.store.remove_word(GLOBAL_SCOPE, "a", "狗 鱼 鸟").unwrap();
+        assert_eq!(db.store.list_pic_words("a").unwrap(), "猫 狗");
+        let tags = db.store.list_pic_tags("a").unwrap();
+        let dog = tags.iter().find(|t| t.word == "狗").unwrap();
+        assert_eq!(dog.scope, "g:1");
+        db.store.remove_word(GLOBAL_SCOPE, "b", "猫").unwrap();
+
+        // 不再被任何图片用到的词一起删掉
+        let words: Vec<String> = select_list(&db.store.conn, "SELECT word FROM t_word ORDER BY id", &[]).unwrap();
+        assert_eq!(words, vec!("猫", "狗"));
+    }
+
+    #[test]
+    fn scopes() {
+        let mut db = TempDb::new("scopes");
+        let group = group_scope("1");
+        let scopes = vec!(group.clone());
+        db.store.append_word(&group, "a", "猫", &Tagger::default()).unwrap();
+        db.store.append_word(GLOBAL_SCOPE, "b", "猫", &Tagger::default()).unwrap();
+        let query = WordQuery::parse("猫");
+        assert_eq!(db.store.query_pic(&[user_scope("2")], &query, Rank::Newest).unwrap(), Vec::<String>::new());
+        assert_eq!(db.store.query_pic(&scopes, &query, Rank::Newest).unwrap(), vec!("a"));
+        assert_eq!(db.store.random_pic(&scopes).unwrap(), "a");
+        assert_eq!(db.store.list_similar_words(&[user_scope("2")], "猫").unwrap(), Vec::<String>::new());
+
+        assert!(db.store.search_global(&group).unwrap());
+        db.store.set_search_global(&group, false).unwrap();
+        assert!(!db.store.search_global(&group).unwrap());
+        db.store.set_search_global(&group, true).unwrap();
+        assert!(db.store.search_global(&group).unwrap());
+    }
+
+    #[test]
+    fn append_and_list() {
+        let mut db = TempDb::new("append");
+        db.store.append_word(GLOBAL_SCOPE, "a", "猫 狗", &Tagger::default()).unwrap();
+        db.store.append_word(GLOBAL_SCOPE, "a", "猫", &Tagger::default()).unwrap();
+        assert_eq!(db.store.list_pic_words("a").unwrap(), "猫 狗");
+        assert_eq!(db.store.list_pic_words("b").unwrap(), "");
+        assert_eq!(db.store.count_pic().unwrap(), 1);
+    }
+
+    #[test]
+    fn query_and_random() {
+        let mut db = TempDb::new("query");
+        assert_eq!(db.store.random_pic(&global()).unwrap(), "");
+        db.store.append_word(GLOBAL_SCOPE, "a", "猫", &Tagger::default()).unwrap();
+        assert_eq!(db.store.random_pic(&global()).unwrap(), "a");
+        db.store.append_word(GLOBAL_SCOPE, "b", "猫 狗", &Tagger::default()).unwrap();
+
+        let mut pics = db.store.query_pic(&global(), &WordQuery::parse("猫"), Rank::Newest).unwrap();
+        pics.sort();
+        assert_eq!(pics, vec!("a", "b"));
+        assert_eq!(db.store.query_pic(&global(), &WordQuery::parse("狗"), Rank::Newest).unwrap(), vec!("b"));
+        assert!(db.store.query_pic(&global(), &WordQuery::parse("鱼"), Rank::Newest).unwrap().is_empty());
+    }
+
+    #[test]
+    fn replace_and_delete() {
+        let mut db = TempDb::new("replace");
+        db.store.append_word(GLOBAL_SCOPE, "a", "猫 狗", &Tagger::default()).unwrap();
+        db.store.replace_word(GLOBAL_SCOPE, "a", "鱼", &Tagger::default()).unwrap();
+        assert_eq!(db.store.list_pic_words("a").unwrap(), "鱼");
+        assert!(db.store.query_pic(&global(), &WordQuery::parse("猫"), Rank::Newest).unwrap().is_empty());
+
+        db.store.delete_pic("a").unwrap();
+        assert_eq!(db.store.count_pic().unwrap(), 0);
+        assert!(db.store.query_pic(&global(), &WordQuery::parse("鱼"), Rank::Newest).unwrap().is_empty());
+    }
+
+    #[test]
+    fn trash() {
+        let mut db = TempDb::new("trash");
+        db.store.append_word(GLOBAL_SCOPE, "a", "猫 狗", &Tagger::default()).unwrap();
+        db.store.append_word(GLOBAL_SCOPE, "b", "狗", &Tagger::default()).unwrap();
+        db.store.delete_pic("a").unwrap();
+        assert_eq!(db.store.count_pic().unwrap(), 1);
+        assert!(db.store.query_pic(&global(), &WordQuery::parse("猫"), Rank::Newest).unwrap().is_empty());
+        assert!(db.store.list_similar_words(&global(), "猫").unwrap().is_empty());
+        let trash: Vec<String> = db.store.list_trash().unwrap().into_iter().map(|t| t.0).collect();
+        assert_eq!(trash, vec!("a"));
+
+        // 拿回来之后词都还在
+        assert!(db.store.restore_pic("a").unwrap());
+        assert!(!db.store.restore_pic("a").unwrap());
+        assert_eq!(db.store.query_pic(&global(), &WordQuery::parse("猫"), Rank::Newest).unwrap(), vec!("a"));
+
+        // 不在回收站里的不能purge，放进去不够久的不会自动purge
+        assert!(!db.store.purge_pic("b").unwrap());
+        db.store.delete_pic("a").unwrap();
+        assert!(db.store.purge_trash(1).unwrap().is_empty());
+        db.store.conn.execute_batch("UPDATE t_pic SET deleted_ts = '2000-01-01 00:00:00.000' WHERE name = 'a'").unwrap();
+        assert_eq!(db.store.purge_trash(1).unwrap(), vec!("a"));
+        assert!(db.store.list_trash().unwrap().is_empty());
+        let words: Vec<String> = select_list(&db.store.conn, "SELECT word FROM t_word ORDER BY id", &[]).unwrap();
+        assert_eq!(words, vec!("狗"));
+
+        db.store.delete_pic("b").unwrap();
+        assert!(db.store.purge_pic("b").unwrap());
+        assert_eq!(db.store.list_pic_words("b").unwrap(), "");
+    }
+
+    #[test]
+    fn word_query() {
+        let mut db = TempDb::new("word-query");
+        db.store.append_word(GLOBAL_SCOPE, "a", "猫 狗", &Tagger::default()).unwrap();
+        db.store.append_word(GLOBAL_SCOPE, "b", "猫 鱼", &Tagger::default()).unwrap();
+        let mut query = |text: &str| {
+            let mut pics = db.store.query_pic(&global(), &WordQuery::parse(text), Rank::Newest).unwrap();
+            pics.sort();
+            pics
+        };
+        assert_eq!(query("猫 狗"), vec!("a"));
+        assert_eq!(query("猫 -狗"), vec!("b"));
+        assert_eq!(query("狗 | 鱼"), vec!("a", "b"));
+        assert!(query("狗 鱼").is_empty());
+        assert!(query("猫 -狗 -鱼").is_empty());
+    }
+
+    #[test]
+    fn list_similar_words() {
+        let mut db = TempDb::new("similar-words");
+        db.store.append_word(GLOBAL_SCOPE, "a", "猫咪 小猫咪 狗狗 大狗狗 小狗 100% 1000", &Tagger::default()).unwrap();
+        let mut words = |word: &str| {
+            let mut words = db.store.list_similar_words(&global(), word).unwrap();
+            words.sort();
+            words
+        };
+        assert_eq!(words("猫"), vec!("小猫咪", "猫咪"));
+        assert_eq!(words("小狗狗"), vec!("大狗狗", "小狗", "小猫咪", "狗狗"));
+        // %和_按原样匹配
+        assert_eq!(words("0%"), vec!("100%"));
+    }
+
+    #[test]
+    fn word_forms() {
+        let mut db = TempDb::new("word-forms");
+        db.store.append_word(GLOBAL_SCOPE, "a", "猫咪 狗", &Tagger::default()).unwrap();
+        for word in ["maomi", "mm", "貓咪"].iter() {
+            assert_eq!(db.store.find_words_by_forms(word).unwrap(), vec!("猫咪"));
+        }
+        assert!(db.store.find_words_by_forms("mao").unwrap().is_empty());
+
+        // 升级前就有的词没有这些写法，补上之后才能查到
+        db.store.conn.execute_batch("UPDATE t_word SET simplified = NULL, pinyin = NULL, initials = NULL").unwrap();
+        assert!(db.store.find_words_by_forms("mm").unwrap().is_empty());
+        assert_eq!(db.store.update_word_forms().unwrap(), 2);
+        assert_eq!(db.store.find_words_by_forms("mm").unwrap(), vec!("猫咪"));
+        assert_eq!(db.store.update_word_forms().unwrap(), 0);
+    }
+
+    #[test]
+    fn synonyms() {
+        let mut db = TempDb::new("synonyms");
+        db.store.add_synonyms(&["猫", "喵"]).unwrap();
+        db.store.add_synonyms(&["狗", "汪"]).unwrap();
+        assert_eq!(db.store.find_synonyms("猫").unwrap(), vec!("喵"));
+
+        // 两组里的词放到一起时两组合并
+        db.store.add_synonyms(&["喵", "汪"]).unwrap();
+        let mut groups = db.store.list_synonyms().unwrap();
+        for t in groups.iter_mut() {
+            t.sort();
+        }
+        assert_eq!(groups, vec!(vec!("喵", "汪", "狗", "猫")));
+
+        assert!(db.store.remove_synonym("狗").unwrap());
+        assert!(!db.store.remove_synonym("狗").unwrap());
+        let mut words = db.store.find_synonyms("猫").unwrap();
+        words.sort();
+        assert_eq!(words, vec!("喵", "汪"));
+    }
+
+    #[test]
+    fn rank() {
+        let mut db = TempDb::new("rank");
+        // last_ts精确到毫秒，每一步之间隔开一点
+        let step = || thread::sleep(Duration::from_millis(5));
+        db.store.append_word(GLOBAL_SCOPE, "a", "猫", &Tagger::default()).unwrap();
+        step();
+        db.store.append_word(GLOBAL_SCOPE, "b", "猫", &Tagger::default()).unwrap();
+        step();
+        assert_eq!(db.store.query_pic(&global(), &WordQuery::parse("猫"), Rank::Newest).unwrap()[0], "b");
+        assert_eq!(db.store.query_pic(&global(), &WordQuery::parse("猫"), Rank::LeastRecent).unwrap()[0], "a");
+
+        // 发过一次图也算最近用过
+        db.store.touch_pic(&global(), "a", "猫").unwrap();
+        step();
+        assert_eq!(db.store.query_pic(&global(), &WordQuery::parse("猫"), Rank::Newest).unwrap()[0], "a");
+        assert_eq!(db.store.query_pic(&global(), &WordQuery::parse("猫"), Rank::LeastRecent).unwrap()[0], "b");
+        db.store.append_word(GLOBAL_SCOPE, "b", "猫", &Tagger::default()).unwrap();
+        assert_eq!(db.store.query_pic(&global(), &WordQuery::parse("猫"), Rank::MostUsed).unwrap()[0], "a");
+        assert_eq!(db.store.query_pic(&global(), &WordQuery::parse("猫"), Rank::Newest).unwrap()[0], "b");
+    }
+
+    #[test]
+    fn rollback_on_failure() {
+        let mut db = TempDb::new("rollback");
+        db.store.append_word(GLOBAL_SCOPE, "a", "猫 狗", &Tagger::default()).unwrap();
+        // 写到一半出错，之前删掉的词要回滚回来
+        db.store.conn.execute_batch(
+            "CREATE TRIGGER t_fail BEFORE INSERT ON t_word WHEN NEW.word = '炸' BEGIN SELECT RAISE(ABORT, 'boom'); END;").unwrap();
+        assert!(db.store.replace_word(GLOBAL_SCOPE, "a", "鱼 炸", &Tagger::default()).is_err());
+        assert_eq!(db.store.list_pic_words("a").unwrap(), "猫 狗");
+        assert!(db.store.append_word(GLOBAL_SCOPE, "b", "鱼 炸", &Tagger::default()).is_err());
+        assert_eq!(db.store.count_pic().unwrap(), 1);
+        assert!(db.store.query_pic(&global(), &WordQuery::parse("鱼"), Rank::Newest).unwrap().is_empty());
+    }
+
+    #[test]
+    fn orphans() {
+        let mut db = TempDb::new("orphans");
+        db.store.append_word(GLOBAL_SCOPE, "a", "猫", &Tagger::default()).unwrap();
+        db.store.replace_word(GLOBAL_SCOPE, "a", "狗", &Tagger::default()).unwrap();
+        db.store.conn.execute_batch(
+            "INSERT INTO t_pic (name) VALUES ('b');
+             INSERT INTO t_pic_word (id_pic, id_word) VALUES (999, 999);").unwrap();
+
+        let orphans = db.store.find_orphans().unwrap();
+        assert_eq!(orphans.pics, vec!("b"));
+        assert_eq!(orphans.words, vec!("猫"));
+        assert_eq!(orphans.pic_words, 1);
+        assert_eq!(db.store.list_pics().unwrap(), vec!("a", "b"));
+
+        db.store.remove_orphans(&orphans).unwrap();
+        let orphans = db.store.find_orphans().unwrap();
+        assert!(orphans.pics.is_empty() && orphans.words.is_empty() && orphans.pic_words == 0);
+        assert_eq!(db.store.list_pic_words("a").unwrap(), "狗");
+    }
+
+    #[test]
+    fn append_word_dedup() {
+        let mut db = TempDb::new("dedup");
+        assert_eq!(db.store.append_word_dedup(GLOBAL_SCOPE, "a", "猫", &Tagger::default(), Some("h1")).unwrap(), None);
+        assert_eq!(db.store.append_word_dedup(GLOBAL_SCOPE, "b", "狗", &Tagger::default(), Some("h1")).unwrap(), Some(String::from("a")));
+        assert_eq!(db.store.list_pic_words("a").unwrap(), "猫 狗");
+        assert_eq!(db.store.resolve_pic("b").unwrap(), "a");
+        assert_eq!(db.store.list_pics().unwrap(), vec!("a"));
+
+        // 合并出错时追加的词也一起回滚
+        db.store.conn.execute_batch(
+            "CREATE TRIGGER t_fail BEFORE INSERT ON t_pic_alias BEGIN SELECT RAISE(ABORT, 'boom'); END;").unwrap();
+        assert!(db.store.append_word_dedup(GLOBAL_SCOPE, "c", "鱼", &Tagger::default(), Some("h1")).is_err());
+        assert_eq!(db.store.list_pics().unwrap(), vec!("a"));
+        assert!(db.store.query_pic(&global(), &WordQuery::parse("鱼"), Rank::Newest).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reopen() {
+        let db = TempDb::new("reopen");
+        let mut store = SqliteStore::open(db.path.to_str().unwrap()).unwrap();
+        store.append_word(GLOBAL_SCOPE, "a", "猫", &Tagger::default()).unwrap();
+        let mut store = SqliteStore::open(db.path.to_str().unwrap()).unwrap();
+        assert_eq!(store.list_pic_words("a").unwrap(), "猫");
+    }
+}
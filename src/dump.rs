@@ -0,0 +1,298 @@
+use csv;
+use db::{DbError, Dump, PicDump, PicStore, PicWordDump, Tagger};
+use serde_json;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::Path;
+
+// 导出文件的格式，jsonl每行一条记录，csv所有记录共用一套列
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Jsonl,
+    Csv,
+}
+
+impl Format {
+    // 没有指定格式时按扩展名判断，.csv以外的都当成jsonl
+    pub fn parse(format: &str, path: &str) -> Option<Format> {
+        let format = if format.is_empty() {
+            Path::new(path).extension().and_then(|t| t.to_str()).unwrap_or("jsonl").to_lowercase()
+        } else {
+            format.to_lowercase()
+        };
+        return match format.as_str() {
+            "csv" => Some(Format::Csv),
+            "jsonl" | "json" => Some(Format::Jsonl),
+            _ => None
+        };
+    }
+}
+
+// 文件里的一条记录，kind决定用到哪些字段：
+// pic: pic hash phash deleted_ts
+// word: pic word scope tagger_id group_id created_ts last_ts use_count
+// alias: alias pic，alias是被合并掉的图片名
+// synonym: word，同一组的同义词以空格分隔
+// scope: scope search_global
+// dict: word，分词用的自定义词
+// 时间都是毫秒时间戳
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Record {
+    kind: String,
+    pic: String,
+    word: String,
+    scope: String,
+    tagger_id: String,
+    group_id: String,
+    created_ts: Option<i64>,
+    last_ts: Option<i64>,
+    use_count: Option<u64>,
+    hash: Option<String>,
+    phash: Option<u64>,
+    deleted_ts: Option<i64>,
+    alias: String,
+    search_global: Option<bool>,
+}
+
+// 把整个图库导出到文件里，返回导出的记录条数
+pub fn export(store: &mut dyn PicStore, path: &str, format: Format) -> std::result::Result<usize, DbError> {
+    let records = to_records(&store.export()?);
+    let file = BufWriter::new(File::create(path)?);
+    match format {
+        Format::Jsonl => write_jsonl(file, &records)?,
+        Format::Csv => write_csv(file, &records)?,
+    }
+    return Ok(records.len());
+}
+
+// 把文件里的数据合并到图库里，已有的数据保留，返回读到的记录条数
+pub fn import(store: &mut dyn PicStore, path: &str, format: Format) -> std::result::Result<usize, DbError> {
+    let file = BufReader::new(File::open(path)?);
+    let records = match format {
+        Format::Jsonl => read_jsonl(file)?,
+        Format::Csv => read_csv(file)?,
+    };
+    store.import(&from_records(&records)?)?;
+    return Ok(records.len());
+}
+
+fn to_records(dump: &Dump) -> Vec<Record> {
+    let mut records = vec!();
+    for t in dump.pics.iter() {
+        records.push(Record {
+            kind: String::from("pic"),
+            pic: t.name.clone(),
+            hash: t.hash.clone(),
+            phash: t.phash,
+            deleted_ts: t.deleted_ts,
+            ..Record::default()
+        });
+    }
+    for t in dump.pic_words.iter() {
+        records.push(Record {
+            kind: String::from("word"),
+            pic: t.pic.clone(),
+            word: t.word.clone(),
+            scope: t.scope.clone(),
+            tagger_id: t.tagger.user_id.clone(),
+            group_id: t.tagger.group_id.clone(),
+            created_ts: t.created_ts,
+            last_ts: Some(t.last_ts),
+            use_count: Some(t.use_count),
+            ..Record::default()
+        });
+    }
+    for (alias, pic) in dump.aliases.iter() {
+        records.push(Record { kind: String::from("alias"), alias: alias.clone(), pic: pic.clone(), ..Record::default() });
+    }
+    for t in dump.synonyms.iter() {
+        records.push(Record { kind: String::from("synonym"), word: t.join(" "), ..Record::default() });
+    }
+    for (scope, search_global) in dump.scopes.iter() {
+        records.push(Record { kind: String::from("scope"), scope: scope.clone(), search_global: Some(*search_global), ..Record::default() });
+    }
+    for t in dump.dict_words.iter() {
+        records.push(Record { kind: String::from("dict"), word: t.clone(), ..Record::default() });
+    }
+    return records;
+}
+
+fn from_records(records: &[Record]) -> Result<Dump> {
+    let mut dump = Dump::default();
+    for (i, t) in records.iter().enumerate() {
+        match t.kind.as_str() {
+            "pic" => dump.pics.push(PicDump {
+                name: required(i, "pic", &t.pic)?,
+                hash: t.hash.clone().filter(|t| !t.is_empty()),
+                phash: t.phash,
+                deleted_ts: t.deleted_ts,
+            }),
+            "word" => dump.pic_words.push(PicWordDump {
+                pic: required(i, "pic", &t.pic)?,
+                word: required(i, "word", &t.word)?,
+                scope: required(i, "scope", &t.scope)?,
+                tagger: Tagger { user_id: t.tagger_id.clone(), group_id: t.group_id.clone() },
+                created_ts: t.created_ts,
+                last_ts: t.last_ts.unwrap_or(0),
+                use_count: t.use_count.unwrap_or(0),
+            }),
+            "alias" => dump.aliases.push((required(i, "alias", &t.alias)?, required(i, "pic", &t.pic)?)),
+            "synonym" => dump.synonyms.push(t.word.split_whitespace().map(String::from).collect()),
+            "scope" => dump.scopes.push((required(i, "scope", &t.scope)?, t.search_global.unwrap_or(true))),
+            "dict" => dump.dict_words.push(required(i, "word", &t.word)?),
+            _ => return Err(invalid(i, &format!("unknown kind: {}", t.kind)))
+        }
+    }
+    return Ok(dump);
+}
+
+fn required(i: usize, field: &str, value: &str) -> Result<String> {
+    if value.is_empty() {
+        return Err(invalid(i, &format!("missing {}", field)));
+    }
+    return Ok(String::from(value));
+}
+
+// i从0开始，报错时给出的是第几条记录
+fn invalid(i: usize, msg: &str) -> Error {
+    return Error::new(ErrorKind::InvalidData, format!("record {}: {}", i + 1, msg));
+}
+
+// 空着的字段不写出来，文件小一些也好读一些
+fn write_jsonl<W: Write>(mut w: W, records: &[Record]) -> Result<()> {
+    for t in records.iter() {
+        let mut value = serde_json::to_value(t)?;
+        if let Some(map) = value.as_object_mut() {
+            map.retain(|_, v| !v.is_null() && v.as_str() != Some(""));
+        }
+        writeln!(w, "{}", value)?;
+    }
+    return w.flush();
+}
+
+fn read_jsonl<R: BufRead>(r: R) -> Result<Vec<Record>> {
+    let mut records = vec!();
+    for (i, line) in r.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(serde_json::from_str(&line).map_err(|e| Error::new(ErrorKind::InvalidData, format!("line {}: {}", i + 1, e)))?);
+    }
+    return Ok(records);
+}
+
+fn write_csv<W: Write>(w: W, records: &[Record]) -> Result<()> {
+    let mut w = csv::Writer::from_writer(w);
+    for t in records.iter() {
+        w.serialize(t)?;
+    }
+    return w.flush();
+}
+
+fn read_csv<R: BufRead>(r: R) -> Result<Vec<Record>> {
+    let mut records = vec!();
+    for t in csv::Reader::from_reader(r).deserialize() {
+        records.push(t?);
+    }
+    return Ok(records);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::{MemoryStore, SqliteStore, GLOBAL_SCOPE};
+    use std::{env, fs, process};
+
+    fn temp_file(name: &str) -> String {
+        let path = env::temp_dir().join(format!("tutuv3-{}-{}", name, process::id()));
+        let _ = fs::remove_file(&path);
+        return String::from(path.to_str().unwrap());
+    }
+
+    // 别名、同义词、范围设置、自定义词、回收站里的图片都要有
+    fn sample() -> MemoryStore {
+        let mut store = MemoryStore::new();
+        let tagger = Tagger { user_id: String::from("2"), group_id: String::from("1") };
+        store.append_word(GLOBAL_SCOPE, "a", "猫 狗", &tagger).unwrap();
+        store.append_word("g:1", "b", "鱼", &Tagger::default()).unwrap();
+        store.append_word(GLOBAL_SCOPE, "c", "鸟,\"逗号\"", &Tagger::default()).unwrap();
+        store.merge_pic("c", "a").unwrap();
+        store.set_pic_hash("a", "h1").unwrap();
+        store.set_pic_phash("a", u64::MAX).unwrap();
+        store.add_synonyms(&["猫", "猫咪", "喵"]).unwrap();
+        store.set_search_global("g:1", false).unwrap();
+        store.add_dict_words(&["天安门", "猫咪"]).unwrap();
+        store.delete_pic("b").unwrap();
+        return store;
+    }
+
+    // 比较时不管记录的先后顺序
+    fn sorted(mut dump: Dump) -> Dump {
+        dump.pics.sort_by(|a, b| a.name.cmp(&b.name));
+        dump.pic_words.sort_by(|a, b| (&a.pic, &a.word, &a.scope).cmp(&(&b.pic, &b.word, &b.scope)));
+        dump.aliases.sort();
+        for t in dump.synonyms.iter_mut() {
+            t.sort();
+        }
+        dump.synonyms.sort();
+        dump.scopes.sort();
+        dump.dict_words.sort();
+        return dump;
+    }
+
+    fn round_trip(format: Format, name: &str) {
+        let mut src = sample();
+        let file = temp_file(name);
+        let db = temp_file(&format!("{}.db", name));
+
+        // 导出后导入到另一种存储里，再导出导入回来，内容不变
+        let count = export(&mut src, &file, format).unwrap();
+        let mut sqlite = SqliteStore::open(&db).unwrap();
+        sqlite.migrate().unwrap();
+        assert_eq!(import(&mut sqlite, &file, format).unwrap(), count);
+        assert_eq!(sorted(sqlite.export().unwrap()), sorted(src.export().unwrap()));
+
+        export(&mut sqlite, &file, format).unwrap();
+        let mut dest = MemoryStore::new();
+        assert_eq!(import(&mut dest, &file, format).unwrap(), count);
+        assert_eq!(sorted(dest.export().unwrap()), sorted(src.export().unwrap()));
+        assert_eq!(dest.resolve_pic("c").unwrap(), "a");
+        assert_eq!(dest.list_trash().unwrap().len(), 1);
+
+        // 再导入一次不会多出东西来
+        import(&mut dest, &file, format).unwrap();
+        assert_eq!(sorted(dest.export().unwrap()), sorted(src.export().unwrap()));
+
+        fs::remove_file(&file).unwrap();
+        fs::remove_file(&db).unwrap();
+    }
+
+    #[test]
+    fn round_trip_jsonl() {
+        round_trip(Format::Jsonl, "dump.jsonl");
+    }
+
+    #[test]
+    fn round_trip_csv() {
+        round_trip(Format::Csv, "dump.csv");
+    }
+
+    #[test]
+    fn parse_format() {
+        assert_eq!(Format::parse("", "a.csv"), Some(Format::Csv));
+        assert_eq!(Format::parse("", "a"), Some(Format::Jsonl));
+        assert_eq!(Format::parse("JSON", "a.csv"), Some(Format::Jsonl));
+        assert_eq!(Format::parse("xml", "a"), None);
+    }
+
+    #[test]
+    fn invalid_record() {
+        let file = temp_file("dump-invalid.jsonl");
+        fs::write(&file, "{\"kind\":\"pic\",\"pic\":\"a\"}\n\n{\"kind\":\"word\",\"pic\":\"a\"}\n").unwrap();
+        let err = import(&mut MemoryStore::new(), &file, Format::Jsonl).unwrap_err();
+        assert!(err.to_string().contains("record 2: missing word"));
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+use image::{self, FilterType};
+use md5;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+// 自己保存的图片文件目录，文件名就是[图片=xxx/]里的xxx
+// set的时候从qq客户端的图片目录里复制一份过来，客户端清了缓存也不会丢
+#[derive(Debug)]
+pub struct ImageStore {
+    dir: PathBuf,
+    // qq客户端保存收到的图片的目录
+    client_dir: Option<PathBuf>,
+}
+
+impl ImageStore {
+    pub fn new(dir: &str, client_dir: &str) -> ImageStore {
+        let client_dir = if client_dir.is_empty() { None } else { Some(PathBuf::from(client_dir)) };
+        return ImageStore { dir: PathBuf::from(dir), client_dir };
+    }
+
+    // 目录下所有图片文件的文件名
+    pub fn list(&self) -> Result<Vec<String>> {
+        let mut names = vec!();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            if let Some(t) = entry.file_name().to_str() {
+                names.push(String::from(t));
+            }
+        }
+        return Ok(names);
+    }
+
+    pub fn remove(&self, name: &str) -> Result<()> {
+        return fs::remove_file(file_path(&self.dir, name)?);
+    }
+
+    // 把qq客户端收到的图片保存一份，返回是否已经有这张图片的文件
+    pub fn save(&self, name: &str) -> Result<bool> {
+        let path = file_path(&self.dir, name)?;
+        if path.is_file() {
+            return Ok(true);
+        }
+
+        let src = match self.client_dir {
+            Some(ref t) => file_path(t, name)?,
+            None => return Ok(false)
+        };
+        if !src.is_file() {
+            return Ok(false);
+        }
+
+        fs::create_dir_all(&self.dir)?;
+        fs::copy(&src, &path)?;
+        return Ok(true);
+    }
+
+    // 图片内容的md5，还没保存过这张图片时返回None
+    pub fn hash(&self, name: &str) -> Result<Option<String>> {
+        let path = file_path(&self.dir, name)?;
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read(&path)?;
+        return Ok(Some(format!("{:x}", md5::compute(&content))));
+    }
+
+    // 图片的感知hash(dHash)，缩放、重新压缩之后基本不变，还没保存过或者解不出来的图片返回None
+    pub fn phash(&self, name: &str) -> Result<Option<u64>> {
+        let path = file_path(&self.dir, name)?;
+        if !path.is_file() {
+            return Ok(None);
+        }
+        // 不看扩展名，按文件内容判断图片格式
+        let content = fs::read(&path)?;
+        let img = match image::load_from_memory(&content) {
+            Ok(t) => t,
+            Err(_) => return Ok(None)
+        };
+
+        // 缩成9x8的灰度图，每一行相邻两个像素比较亮度，得到64位
+        let img = img.resize_exact(9, 8, FilterType::Triangle).to_luma();
+        let mut hash = 0u64;
+        for y in 0..8 {
+            for x in 0..8 {
+                hash <<= 1;
+                if img.get_pixel(x, y).data[0] < img.get_pixel(x + 1, y).data[0] {
+                    hash |= 1;
+                }
+            }
+        }
+        return Ok(Some(hash));
+    }
+
+    // 发图之前调用，qq客户端那边的文件没了的话从自己保存的文件恢复过去，返回图片是否还能发出去
+    pub fn fetch(&self, name: &str) -> Result<bool> {
+        let path = file_path(&self.dir, name)?;
+
+        let dst = match self.client_dir {
+            Some(ref t) => file_path(t, name)?,
+            None => return Ok(path.is_file())
+        };
+        if dst.is_file() {
+            return Ok(true);
+        }
+        if !path.is_file() {
+            return Ok(false);
+        }
+
+        fs::copy(&path, &dst)?;
+        return Ok(true);
+    }
+}
+
+// 图片名来自聊天消息，不能让它跳出图片目录
+fn file_path(dir: &Path, name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\\') {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("bad pic name: {}", name)));
+    }
+    return Ok(dir.join(name));
+}
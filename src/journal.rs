@@ -0,0 +1,182 @@
+use db::{DbError, PicStore, TagRecord};
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+
+// 每个人在每个会话里最多记这么多次操作，更早的就不能撤销了
+const MAX_PER_USER: usize = 10;
+
+// 对图片的词做过的修改，撤销时要用到的数据都记在这里
+#[derive(Debug)]
+pub struct Operation {
+    pub id: u64,
+    pub session_key: String,
+    pub user_id: String,
+    pub kind: OperationKind,
+    pub pic: String,
+    pub scope: String,
+    // 这次新加上的词，撤销时从scope里去掉
+    pub added: Vec<String>,
+    // 这次去掉的词，撤销时按原来的范围和人加回去
+    pub removed: Vec<TagRecord>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperationKind {
+    Set,
+    Unset,
+    Replace,
+    Delete,
+}
+
+impl Operation {
+    // 还没有加上词和去掉的词，id在Journal::record时分配
+    pub fn new(session_key: &str, user_id: &str, kind: OperationKind, pic: &str, scope: &str) -> Operation {
+        return Operation {
+            id: 0,
+            session_key: String::from(session_key),
+            user_id: String::from(user_id),
+            kind,
+            pic: String::from(pic),
+            scope: String::from(scope),
+            added: vec!(),
+            removed: vec!(),
+        };
+    }
+
+    // 列出来给管理员看的一行
+    pub fn describe(&self) -> String {
+        let kind = match self.kind {
+            OperationKind::Set => "set",
+            OperationKind::Unset => "unset",
+            OperationKind::Replace => "replace",
+            OperationKind::Delete => "delete",
+        };
+        let mut text = format!("#{} {} by {} in {} on {}", self.id, kind, self.user_id, self.session_key, self.pic);
+        if !self.added.is_empty() {
+            text.push_str(&format!(" +{}", self.added.join(" +")));
+        }
+        if !self.removed.is_empty() {
+            let words: Vec<&str> = self.removed.iter().map(|t| t.word.as_str()).collect();
+            text.push_str(&format!(" -{}", words.join(" -")));
+        }
+        return text;
+    }
+
+    // 先去掉加上的词，再把去掉的词加回去
+    pub fn undo(&self, store: &mut dyn PicStore) -> Result<(), DbError> {
+        // 图片可能在这之后被合并到了别的图片上
+        let pic = store.resolve_pic(&self.pic)?;
+
+        // delete的图片还在回收站里的话直接拿回来，已经被purge了的按原来的词重新加上
+        if self.kind == OperationKind::Delete && store.restore_pic(&pic)? {
+            return Ok(());
+        }
+        if !self.added.is_empty() {
+            store.remove_word(&self.scope, &pic, &self.added.join(" "))?;
+        }
+        for t in self.removed.iter() {
+            store.append_word(&t.scope, &pic, &t.word, &t.tagger)?;
+        }
+        return Ok(());
+    }
+}
+
+// 按会话记下来的操作，会话的key与process_request里的一致
+#[derive(Debug)]
+pub struct Journal {
+    next_id: u64,
+    sessions: HashMap<String, VecDeque<Operation>>,
+}
+
+impl Journal {
+    pub fn new() -> Journal {
+        return Journal { next_id: 1, sessions: HashMap::new() };
+    }
+
+    pub fn record(&mut self, mut op: Operation) {
+        if op.added.is_empty() && op.removed.is_empty() {
+            return;
+        }
+
+        op.id = self.next_id;
+        self.next_id += 1;
+
+        let user_id = op.user_id.clone();
+        let list = self.sessions.entry(op.session_key.clone()).or_default();
+        list.push_back(op);
+
+        // 超出的部分从这个人最早的操作开始丢掉
+        if list.iter().filter(|t| t.user_id == user_id).count() > MAX_PER_USER {
+            if let Some(i) = list.iter().position(|t| t.user_id == user_id) {
+                list.remove(i);
+            }
+        }
+    }
+
+    // 这个人在这个会话里的最后一次操作
+    pub fn last(&self, session_key: &str, user_id: &str) -> Option<&Operation> {
+        return self.sessions.get(session_key)?.iter().rev().find(|t| t.user_id == user_id);
+    }
+
+    // 任意会话里的某一次操作
+    pub fn get(&self, id: u64) -> Option<&Operation> {
+        return self.sessions.values().flat_map(|t| t.iter()).find(|t| t.id == id);
+    }
+
+    // 撤销成功之后从记录里去掉，不能再撤销第二次
+    pub fn remove(&mut self, id: u64) {
+        for list in self.sessions.values_mut() {
+            list.retain(|t| t.id != id);
+        }
+    }
+
+    // 所有会话里的操作，最近的在前
+    pub fn list(&self) -> Vec<&Operation> {
+        let mut list: Vec<&Operation> = self.sessions.values().flat_map(|t| t.iter()).collect();
+        list.sort_by_key(|t| Reverse(t.id));
+        return list;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(user_id: &str, word: &str) -> Operation {
+        return Operation { added: vec!(String::from(word)), ..Operation::new("g:1", user_id, OperationKind::Set, "a", "g:1") };
+    }
+
+    #[test]
+    fn record() {
+        let mut journal = Journal::new();
+        // 什么都没改的操作不记
+        journal.record(Operation::new("g:1", "2", OperationKind::Set, "a", "g:1"));
+        assert!(journal.list().is_empty());
+
+        journal.record(set("2", "猫"));
+        journal.record(set("3", "狗"));
+        assert_eq!(journal.last("g:1", "2").unwrap().id, 1);
+        assert_eq!(journal.last("g:1", "3").unwrap().id, 2);
+        assert!(journal.last("g:2", "2").is_none());
+        let ids: Vec<u64> = journal.list().iter().map(|t| t.id).collect();
+        assert_eq!(ids, vec!(2, 1));
+
+        journal.remove(1);
+        assert!(journal.get(1).is_none());
+        assert!(journal.last("g:1", "2").is_none());
+    }
+
+    #[test]
+    fn bounded_per_user() {
+        let mut journal = Journal::new();
+        journal.record(set("3", "狗"));
+        for i in 0..MAX_PER_USER + 2 {
+            journal.record(set("2", &i.to_string()));
+        }
+        // 只丢掉这个人最早的操作，别人的不受影响
+        let ids: Vec<u64> = journal.list().iter().filter(|t| t.user_id == "2").map(|t| t.id).collect();
+        assert_eq!(ids.len(), MAX_PER_USER);
+        assert_eq!(*ids.last().unwrap(), 4);
+        assert!(journal.get(1).is_some());
+    }
+}
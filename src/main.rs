@@ -1,10 +1,35 @@
+// 函数最后也写return，整个项目都是这个写法
+#![allow(clippy::needless_return)]
+
 #[macro_use(params)]
 extern crate mysql;
+extern crate chrono;
+extern crate md5;
+extern crate image;
+extern crate rusqlite;
+#[macro_use]
+extern crate serde;
+extern crate serde_json;
+extern crate csv;
+extern crate jieba_rs;
 
+use backup::Backup;
+use db::{DbError, PicStore};
+use image_store::ImageStore;
+use search::SearchIndex;
+use segment::Segmenter;
 use std::env;
+use std::process;
+use std::sync::{Arc, Mutex};
 
+mod backup;
 mod bot;
 mod db;
+mod dump;
+mod image_store;
+mod journal;
+mod search;
+mod segment;
 mod web;
 
 fn main() {
@@ -13,19 +38,165 @@ fn main() {
     let host = find_arg(&args, "host", "0.0.0.0");
     let port = find_arg(&args, "port", "8080");
     let admin_id = find_arg(&args, "admin", "280710651");
-    let db_user = find_arg(&args, "db_user", "root");
-    let db_pwd = find_arg(&args, "db_pwd", "");
 
-    db::init(&db_user, &db_pwd);
-    web::start(host, port, &mut bot::BotGlobals::new(admin_id, db_user, db_pwd));
+    let mut store = match open_store(&args) {
+        Ok(t) => t,
+        Err(t) => {
+            println!("open db fail: {}", t);
+            process::exit(1);
+        }
+    };
+    if let Err(t) = db::init(&mut *store) {
+        println!("init db fail: {}", t);
+        process::exit(1);
+    }
+
+    // migrate模式只建表/升级表结构，不启动web服务
+    if has_arg(&args, "migrate") {
+        return;
+    }
+
+    // export/import模式把整个图库导出到文件或者从文件合并进来，不启动web服务
+    let export_file = find_arg(&args, "export", "");
+    let import_file = find_arg(&args, "import", "");
+    if !export_file.is_empty() || !import_file.is_empty() {
+        let (mode, path) = if export_file.is_empty() { ("import", &import_file) } else { ("export", &export_file) };
+        let format = match dump::Format::parse(&find_arg(&args, "format", ""), path) {
+            Some(t) => t,
+            None => {
+                println!("unknown format, should be jsonl or csv");
+                process::exit(1);
+            }
+        };
+        let result = if mode == "import" { dump::import(&mut *store, path, format) } else { dump::export(&mut *store, path, format) };
+        match result {
+            Ok(t) => println!("{} ok: {} records", mode, t),
+            Err(t) => {
+                println!("{} fail: {}", mode, t);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // 自己保存图片文件的目录，不设置就不管理图片文件
+    // qq_pic_dir是qq客户端保存收到的图片的目录，set时从这里复制图片
+    let pic_dir = find_arg(&args, "pic_dir", "");
+    let qq_pic_dir = find_arg(&args, "qq_pic_dir", "");
+    let images = if pic_dir.is_empty() { None } else { Some(ImageStore::new(&pic_dir, &qq_pic_dir)) };
+
+    // 回收站里的图片放了这么多天之后彻底删除，0表示不自动删除
+    let trash_days = find_arg(&args, "trash_days", "30").parse().unwrap_or(30);
+
+    // 定时把整个图库备份到backup_dir里，按份数和天数清理旧的备份
+    let backup_dir = find_arg(&args, "backup_dir", "");
+    let backup = if backup_dir.is_empty() {
+        None
+    } else {
+        Some(Backup::new(
+            &backup_dir,
+            find_arg(&args, "backup_hours", "24").parse().unwrap_or(24),
+            find_arg(&args, "backup_keep", "7").parse().unwrap_or(7),
+            find_arg(&args, "backup_keep_days", "30").parse().unwrap_or(30),
+        ))
+    };
+
+    // 启动前先把指定的备份恢复到空的数据库里，restore=latest表示最新的一份
+    let restore = find_arg(&args, "restore", "");
+    if !restore.is_empty() {
+        let result = match backup {
+            Some(ref t) => t.restore(&restore, &mut *store),
+            None => {
+                println!("restore fail: no backup_dir");
+                process::exit(1);
+            }
+        };
+        match result {
+            Ok(t) => println!("restore ok: {} {} records", t.0, t.1),
+            Err(t) => {
+                println!("restore fail: {}", t);
+                process::exit(1);
+            }
+        }
+    }
+
+    // 定时备份用另一个数据库连接，demo模式下数据只在这个进程的内存里，只能用backup命令手动备份
+    let backup = backup.map(|t| Arc::new(Mutex::new(t)));
+    if let Some(ref t) = backup {
+        if has_arg(&args, "--demo") {
+            println!("scheduled backup is disabled in demo mode");
+        } else {
+            let args = args.clone();
+            backup::start(t.clone(), move || open_store(&args));
+        }
+    }
+
+    // search=index时用内存里的倒排索引查询，按命中的词打分，默认直接查数据库
+    let search = if find_arg(&args, "search", "sql") == "index" { Some(SearchIndex::new()) } else { None };
+
+    // segment=on时set和查询都先做中文分词
+    let segmenter = if find_arg(&args, "segment", "off") == "on" {
+        match Segmenter::new(&mut *store) {
+            Ok(t) => Some(t),
+            Err(t) => {
+                println!("load dict fail: {}", t);
+                process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    web::start(host, port, &mut bot::BotGlobals::new(admin_id, store, images, trash_days, backup, search, segmenter));
 }
 
-fn find_arg<'a>(args: &'a Vec<String>, key: &str, default_value: &'a str) -> String {
+fn open_store(args: &[String]) -> Result<Box<dyn PicStore>, DbError> {
+    // demo模式下数据只保存在内存里，不需要任何数据库
+    if has_arg(args, "--demo") {
+        return Ok(Box::new(db::MemoryStore::new()));
+    }
+
+    let db_type = find_arg(args, "db_type", "mysql");
+    return match db_type.as_str() {
+        "sqlite" => {
+            let db_file = find_arg(args, "db_file", "tutu.db");
+            Ok(Box::new(db::SqliteStore::open(&db_file)?))
+        }
+        _ => {
+            let db_user = find_arg(args, "db_user", "root");
+            let db_pwd = find_arg(args, "db_pwd", "");
+
+            let mut db = db::DbInfo::new(&db_user, &db_pwd);
+            db.url = find_arg(args, "db_url", "");
+            db.host = find_arg(args, "db_host", "localhost");
+            db.port = find_arg(args, "db_port", "3306").parse().unwrap_or(db.port);
+            db.db_name = find_arg(args, "db_name", "tutu");
+            db.socket = find_arg(args, "db_socket", "");
+            db.ssl = find_arg(args, "db_ssl", "false") == "true";
+            db.ssl_ca = find_arg(args, "db_ssl_ca", "");
+            db.ssl_pkcs12 = find_arg(args, "db_ssl_pkcs12", "");
+            db.ssl_pkcs12_pwd = find_arg(args, "db_ssl_pkcs12_pwd", "");
+            db.ssl_skip_verify = find_arg(args, "db_ssl_skip_verify", "false") == "true";
+            db.pool_min = find_arg(args, "db_pool_min", "1").parse().unwrap_or(db.pool_min);
+            db.pool_max = find_arg(args, "db_pool_max", "10").parse().unwrap_or(db.pool_max);
+            db.pool_timeout_ms = find_arg(args, "db_pool_timeout", "3000").parse().unwrap_or(db.pool_timeout_ms);
+            db.pool_check_health = find_arg(args, "db_pool_check", "true") != "false";
+            Ok(Box::new(db::MysqlStore::new(db)?))
+        }
+    };
+}
+
+fn find_arg<'a>(args: &'a [String], key: &str, default_value: &'a str) -> String {
+    let prefix = format!("{}=", key);
     for arg in args.iter() {
-        if arg.starts_with(key) {
-            return String::from(&arg[key.len() + 1..]);
+        if arg.starts_with(&prefix) {
+            return String::from(&arg[prefix.len()..]);
         }
     }
 
     return String::from(default_value);
 }
+
+fn has_arg(args: &[String], key: &str) -> bool {
+    return args.iter().any(|t| t == key);
+}
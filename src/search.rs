@@ -0,0 +1,355 @@
+use db::{DbError, PicStore, PicWordDump, Rank, WordQuery};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 内存里的倒排索引，可以代替数据库按词完全匹配的查询
+// 词切成若干个term，查询时按命中的term的稀有程度给图片打分，中文按字和相邻两个字切分，查词的一部分也能查到
+// 图片上的词有变化时只从数据库重新读这一张图片，撤销、合并这样一次改动很多图片的才标记一下，下次查询时整个重建
+pub struct SearchIndex {
+    stale: bool,
+    // 图片 -> 图片上的词，回收站里的图片不在里面
+    pics: HashMap<String, Vec<Link>>,
+    // term -> 有这个term的图片，图片数用来算idf
+    terms: HashMap<String, HashSet<String>>,
+    // 关联加进索引的顺序，与数据库里last_ts相同时按id区分先后一样
+    next_seq: u64,
+    seed: u64,
+}
+
+// 图片上的一个词，last_ts和use_count用来在得分相同的图片里按Rank挑选
+struct Link {
+    word: String,
+    scope: String,
+    last_ts: i64,
+    use_count: u64,
+    seq: u64,
+    terms: Vec<String>,
+}
+
+// 一张图片命中的情况
+#[derive(Default)]
+struct Hit {
+    // 命中了几组查询条件
+    groups: usize,
+    score: f64,
+    last_ts: i64,
+    use_count: u64,
+    last_seq: u64,
+}
+
+impl SearchIndex {
+    pub fn new() -> SearchIndex {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|t| t.as_secs() ^ t.subsec_nanos() as u64)
+            .unwrap_or(1);
+        return SearchIndex { stale: true, pics: HashMap::new(), terms: HashMap::new(), next_seq: 0, seed: seed | 1 };
+    }
+
+    // 很多图片都有了变化，下次查询前重建
+    pub fn mark_stale(&mut self) {
+        self.stale = true;
+    }
+
+    // 一张图片上的词有了变化，重新读这张图片的
+    pub fn refresh_pic(&mut self, pic: &str, store: &mut dyn PicStore) -> Result<(), DbError> {
+        if self.stale {
+            return Ok(());
+        }
+        self.remove_pic(pic);
+        let links = store.list_pic_links(Some(pic))?;
+        self.add_links(links);
+        return Ok(());
+    }
+
+    fn rebuild(&mut self, store: &mut dyn PicStore) -> Result<(), DbError> {
+        let links = store.list_pic_links(None)?;
+        self.pics.clear();
+        self.terms.clear();
+        self.add_links(links);
+        self.stale = false;
+        return Ok(());
+    }
+
+    fn remove_pic(&mut self, pic: &str) {
+        let links = match self.pics.remove(pic) {
+            Some(t) => t,
+            None => return
+        };
+        for term in links.iter().flat_map(|t| t.terms.iter()) {
+            let empty = match self.terms.get_mut(term) {
+                Some(t) => {
+                    t.remove(pic);
+                    t.is_empty()
+                }
+                None => false
+            };
+            if empty {
+                self.terms.remove(term);
+            }
+        }
+    }
+
+    fn add_links(&mut self, links: Vec<PicWordDump>) {
+        for t in links {
+            let terms = index_terms(&t.word);
+            for term in terms.iter() {
+                self.terms.entry(term.clone()).or_default().insert(t.pic.clone());
+            }
+            let seq = self.next_seq;
+            self.next_seq += 1;
+            self.pics.entry(t.pic).or_default()
+                .push(Link { word: t.word, scope: t.scope, last_ts: t.last_ts, use_count: t.use_count, seq, terms });
+        }
+    }
+
+    // 与PicStore::query_pic一样，返回最合适的一张图片，以及命中了的图片里随机的另一张
+    // 只有每组查询条件都命中了的图片才算命中，先看命中的term的idf之和，一样时再按rank
+    pub fn query_pic(&mut self, scopes: &[String], query: &WordQuery, rank: Rank, store: &mut dyn PicStore) -> Result<Vec<String>, DbError> {
+        if self.stale {
+            self.rebuild(store)?;
+        }
+        if query.is_empty() {
+            return Ok(vec!());
+        }
+
+        // 排除的词按整个词比较
+        let excluded: HashSet<&str> = self.pics.iter()
+            .filter(|(_, links)| links.iter().any(|t| scopes.contains(&t.scope) && query.none.contains(&t.word)))
+            .map(|t| t.0.as_str())
+            .collect();
+
+        let mut hits: HashMap<&str, Hit> = HashMap::new();
+        // 一个词可能命中好几个term，use_count只算一次
+        let mut seen: HashSet<u64> = HashSet::new();
+        for words in query.all.iter() {
+            let mut terms: Vec<String> = vec!();
+            for term in words.iter().flat_map(|t| query_terms(t)) {
+                if !terms.contains(&term) {
+                    terms.push(term);
+                }
+            }
+
+            // 一组里的词是"或"的关系，命中其中任何一个term都算命中这一组
+            let mut matched: HashSet<&str> = HashSet::new();
+            for term in terms.iter() {
+                let pics = match self.terms.get(term) {
+                    Some(t) => t,
+                    None => continue
+                };
+                let idf = self.idf(pics.len());
+                for pic in pics.iter().map(|t| t.as_str()).filter(|t| !excluded.contains(t)) {
+                    let links: Vec<&Link> = self.pics[pic].iter()
+                        .filter(|t| scopes.contains(&t.scope) && t.terms.contains(term))
+                        .collect();
+                    if links.is_empty() {
+                        continue;
+                    }
+                    let hit = hits.entry(pic).or_default();
+                    for link in links {
+                        if seen.insert(link.seq) {
+                            hit.last_ts = hit.last_ts.max(link.last_ts);
+                            hit.use_count += link.use_count;
+                            hit.last_seq = hit.last_seq.max(link.seq);
+                        }
+                    }
+                    hit.score += idf;
+                    matched.insert(pic);
+                }
+            }
+            for pic in matched {
+                if let Some(t) = hits.get_mut(pic) {
+                    t.groups += 1;
+                }
+            }
+        }
+
+        let mut candidates: Vec<(&str, &Hit)> = hits.iter()
+            .filter(|t| t.1.groups == query.all.len())
+            .map(|(k, v)| (*k, v))
+            .collect();
+        if candidates.is_empty() {
+            return Ok(vec!());
+        }
+        candidates.sort_by(|a, b| {
+            let (a, b) = (a.1, b.1);
+            b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal)
+                .then(match rank {
+                    Rank::Newest => (b.last_ts, b.last_seq).cmp(&(a.last_ts, a.last_seq)),
+                    Rank::MostUsed => (b.use_count, b.last_ts, b.last_seq).cmp(&(a.use_count, a.last_ts, a.last_seq)),
+                    Rank::LeastRecent => (a.last_ts, a.last_seq).cmp(&(b.last_ts, b.last_seq)),
+                })
+        });
+        let mut pics: Vec<String> = candidates.into_iter().map(|t| String::from(t.0)).collect();
+
+        if pics.len() == 1 {
+            return Ok(pics);
+        }
+        let other = 1 + self.rand(pics.len() - 1);
+        let other = pics.swap_remove(other);
+        pics.truncate(1);
+        pics.push(other);
+        return Ok(pics);
+    }
+
+    // 与PicStore::touch_pic一样刷新索引里的last_ts和use_count，不用为此重建
+    pub fn touch_pic(&mut self, scopes: &[String], pic: &str, word: &str) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|t| t.as_millis() as i64).unwrap_or(0);
+        if let Some(links) = self.pics.get_mut(pic) {
+            for t in links.iter_mut().filter(|t| t.word == word && scopes.contains(&t.scope)) {
+                t.last_ts = t.last_ts.max(now);
+                t.use_count += 1;
+            }
+        }
+    }
+
+    // 越少的图片用到这个term，命中它的图片得分越高
+    fn idf(&self, count: usize) -> f64 {
+        return (1.0 + self.pics.len() as f64 / count.max(1) as f64).ln();
+    }
+
+    fn rand(&mut self, n: usize) -> usize {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        return (self.seed % n as u64) as usize;
+    }
+}
+
+// 建索引时一个词切出来的term：整个词，中日韩文字的每个字和相邻的两个字，字母数字连在一起的一段
+fn index_terms(word: &str) -> Vec<String> {
+    return tokenize(word, true);
+}
+
+// 查询时只用相邻的两个字，只有一个字时才用单字，不然查"猫咪"会把只有"猫"的图片也查出来
+fn query_terms(word: &str) -> Vec<String> {
+    return tokenize(word, false);
+}
+
+fn tokenize(word: &str, unigrams: bool) -> Vec<String> {
+    let word = word.to_lowercase();
+    let mut terms = vec!(word.clone());
+    let mut cjk: Vec<char> = vec!();
+    let mut other = String::new();
+    // 末尾补一个空格，把最后一段也切出来
+    for c in word.chars().chain(Some(' ')) {
+        if is_cjk(c) {
+            push(&mut terms, other.clone());
+            other.clear();
+            cjk.push(c);
+            continue;
+        }
+
+        if cjk.len() == 1 || (unigrams && !cjk.is_empty()) {
+            for t in cjk.iter() {
+                push(&mut terms, t.to_string());
+            }
+        }
+        for t in cjk.windows(2) {
+            push(&mut terms, t.iter().collect());
+        }
+        cjk.clear();
+
+        if c.is_alphanumeric() {
+            other.push(c);
+        } else {
+            push(&mut terms, other.clone());
+            other.clear();
+        }
+    }
+    return terms;
+
+    fn push(terms: &mut Vec<String>, term: String) {
+        if !term.is_empty() && !terms.contains(&term) {
+            terms.push(term);
+        }
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    return matches!(c as u32, 0x3040..=0x30FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xAC00..=0xD7AF | 0xF900..=0xFAFF | 0x20000..=0x2FA1F);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::{MemoryStore, Tagger, GLOBAL_SCOPE};
+
+    fn global() -> Vec<String> {
+        return vec!(String::from(GLOBAL_SCOPE));
+    }
+
+    fn set(store: &mut MemoryStore, pic: &str, word: &str) {
+        store.append_word(GLOBAL_SCOPE, pic, word, &Tagger::default()).unwrap();
+    }
+
+    fn first(index: &mut SearchIndex, text: &str, store: &mut MemoryStore) -> Option<String> {
+        return index.query_pic(&global(), &WordQuery::parse(text), Rank::Newest, store).unwrap().into_iter().next();
+    }
+
+    #[test]
+    fn tokenize_cjk() {
+        // 建索引时中文切成单字和相邻的两个字
+        assert_eq!(index_terms("小猫咪"), vec!("小猫咪", "小", "猫", "咪", "小猫", "猫咪"));
+        // 查询时只用相邻的两个字
+        assert_eq!(query_terms("小猫咪"), vec!("小猫咪", "小猫", "猫咪"));
+        // 只有一个字时才用单字
+        assert_eq!(query_terms("猫"), vec!("猫"));
+        assert_eq!(query_terms("猫 狗"), vec!("猫 狗", "猫", "狗"));
+        // 字母数字连在一起的一段是一个term，大小写不分
+        assert_eq!(index_terms("Cat猫2"), vec!("cat猫2", "cat", "猫", "2"));
+        assert_eq!(query_terms("hello-world"), vec!("hello-world", "hello", "world"));
+    }
+
+    #[test]
+    fn idf_ranking() {
+        let mut store = MemoryStore::new();
+        set(&mut store, "d", "狗");
+        set(&mut store, "a", "猫");
+        set(&mut store, "b", "猫");
+        set(&mut store, "c", "猫");
+        let mut index = SearchIndex::new();
+
+        // 命中越少图片用到的term得分越高，比最近set的更靠前
+        assert_eq!(first(&mut index, "猫|狗", &mut store), Some(String::from("d")));
+        // 得分一样时按rank
+        assert_eq!(first(&mut index, "猫", &mut store), Some(String::from("c")));
+        let pics = index.query_pic(&global(), &WordQuery::parse("猫"), Rank::LeastRecent, &mut store).unwrap();
+        assert_eq!(pics[0], "a");
+        assert_eq!(pics.len(), 2);
+
+        // 每组查询条件都要命中
+        assert_eq!(first(&mut index, "猫 狗", &mut store), None);
+        assert_eq!(first(&mut index, "猫 -猫", &mut store), None);
+        assert!(index.query_pic(&[String::from("g:1")], &WordQuery::parse("猫"), Rank::Newest, &mut store).unwrap().is_empty());
+    }
+
+    #[test]
+    fn refresh_pic() {
+        let mut store = MemoryStore::new();
+        set(&mut store, "a", "猫咪");
+        let mut index = SearchIndex::new();
+        assert_eq!(first(&mut index, "猫咪", &mut store), Some(String::from("a")));
+
+        // 没有刷新的图片索引里看不到
+        set(&mut store, "b", "小猫咪");
+        assert_eq!(first(&mut index, "小猫", &mut store), None);
+        index.refresh_pic("b", &mut store).unwrap();
+        assert_eq!(first(&mut index, "小猫", &mut store), Some(String::from("b")));
+
+        // 放进回收站的图片从索引里去掉
+        store.delete_pic("b").unwrap();
+        index.refresh_pic("b", &mut store).unwrap();
+        assert_eq!(first(&mut index, "小猫", &mut store), None);
+        assert!(!index.terms.contains_key("小猫"));
+
+        // 合并之后两张图片都要刷新
+        set(&mut store, "c", "狗");
+        index.refresh_pic("c", &mut store).unwrap();
+        store.merge_pic("c", "a").unwrap();
+        index.refresh_pic("c", &mut store).unwrap();
+        index.refresh_pic("a", &mut store).unwrap();
+        assert_eq!(index.query_pic(&global(), &WordQuery::parse("狗"), Rank::Newest, &mut store).unwrap(), vec!("a"));
+        assert!(!index.pics.contains_key("c"));
+    }
+}
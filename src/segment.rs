@@ -0,0 +1,107 @@
+use db::{DbError, PicStore, WordQuery};
+use jieba_rs::Jieba;
+
+// 切出来之后单独成词没有意义的字，直接去掉
+const STOP_WORDS: &[&str] = &["的", "地", "得", "了", "着", "过", "吗", "呢", "吧", "啊", "呀", "哦", "和", "与", "及"];
+
+// 中文分词，用jieba自带的词典，再加上管理员加的自定义词
+// set时把一句话切成几个词保存，查询时也切开再查
+pub struct Segmenter {
+    jieba: Jieba,
+}
+
+impl Segmenter {
+    pub fn new(store: &mut dyn PicStore) -> Result<Segmenter, DbError> {
+        let mut segmenter = Segmenter { jieba: Jieba::new() };
+        for word in store.list_dict_words()? {
+            segmenter.add_word(&word);
+        }
+        return Ok(segmenter);
+    }
+
+    // 加上之后这个词不会再被拆开
+    pub fn add_word(&mut self, word: &str) {
+        self.jieba.add_word(word, None, None);
+    }
+
+    // jieba不能去掉已经加上的词，只能重新加载整个词典
+    pub fn reload(&mut self, store: &mut dyn PicStore) -> Result<(), DbError> {
+        *self = Segmenter::new(store)?;
+        return Ok(());
+    }
+
+    // 空白分隔的每一段再分别切开，去掉标点和没有意义的字，整段都去掉了的保留原样
+    pub fn split(&self, text: &str) -> Vec<String> {
+        let mut words: Vec<String> = vec!();
+        for part in text.split_whitespace() {
+            let mut cut: Vec<&str> = self.jieba.cut(part, true).into_iter()
+                .map(|t| t.trim())
+                .filter(|t| t.chars().any(char::is_alphanumeric) && !STOP_WORDS.contains(t))
+                .collect();
+            if cut.is_empty() {
+                cut.push(part);
+            }
+            for word in cut {
+                if !words.iter().any(|t| t == word) {
+                    words.push(String::from(word));
+                }
+            }
+        }
+        return words;
+    }
+
+    // set时保存的词，以空格分隔
+    pub fn split_tags(&self, text: &str) -> String {
+        return self.split(text).join(" ");
+    }
+
+    // 只有一个词的条件切开之后每个词各自作为一个条件，原来的整个词作为每个条件的另一个选择，以前set的没切开的词也能查到
+    // a|b这样的条件和排除的词不切
+    pub fn split_query(&self, query: &WordQuery) -> WordQuery {
+        let mut result = WordQuery { all: vec!(), none: query.none.clone() };
+        for words in query.all.iter() {
+            let parts = if words.len() == 1 { self.split(&words[0]) } else { vec!() };
+            if parts.len() < 2 {
+                result.all.push(words.clone());
+                continue;
+            }
+            for part in parts {
+                result.all.push(vec!(part, words[0].clone()));
+            }
+        }
+        return result;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::MemoryStore;
+
+    #[test]
+    fn split() {
+        let mut store = MemoryStore::new();
+        let mut segmenter = Segmenter::new(&mut store).unwrap();
+        assert_eq!(segmenter.split_tags("我爱北京天安门"), "我 爱 北京 天安门");
+        // 标点和没有意义的字去掉，重复的只留一个，整段都去掉了的保留原样
+        assert_eq!(segmenter.split_tags("北京的天安门！ 北京 的"), "北京 天安门 的");
+
+        // 自定义词不再被拆开，去掉之后重新加载
+        assert_eq!(segmenter.split_tags("好开心猫猫"), segmenter.split_tags("好开心猫猫"));
+        store.add_dict_words(&["好开心猫猫"]).unwrap();
+        segmenter.reload(&mut store).unwrap();
+        assert_eq!(segmenter.split_tags("好开心猫猫"), "好开心猫猫");
+        store.remove_dict_word("好开心猫猫").unwrap();
+        segmenter.reload(&mut store).unwrap();
+        assert_ne!(segmenter.split_tags("好开心猫猫"), "好开心猫猫");
+    }
+
+    #[test]
+    fn split_query() {
+        let segmenter = Segmenter::new(&mut MemoryStore::new()).unwrap();
+        let query = segmenter.split_query(&WordQuery::parse("北京天安门 猫|狗 -我爱北京"));
+        let all: Vec<Vec<&str>> = query.all.iter().map(|t| t.iter().map(|t| t.as_str()).collect()).collect();
+        assert_eq!(all, vec!(vec!("北京", "北京天安门"), vec!("天安门", "北京天安门"), vec!("猫", "狗")));
+        assert_eq!(query.none, vec!("我爱北京"));
+    }
+}
@@ -6,7 +6,7 @@ use std::collections::HashMap;
 use std::io::*;
 use std::net::{Shutdown, TcpListener, TcpStream};
 
-const HEADER_CONTENT_LENGTH: &'static str = "Content-Length: ";
+const HEADER_CONTENT_LENGTH: &str = "Content-Length: ";
 
 pub fn start(host: String, port: String, globals: &mut BotGlobals) {
     let listener = TcpListener::bind(format!("{}:{}", host, port)).unwrap();